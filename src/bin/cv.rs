@@ -1,6 +1,6 @@
 use ensembles_rs::data_frame;
 use ensembles_rs::tree;
-use ensembles_rs::tree::DecisionTreeConfig;
+use ensembles_rs::tree::{DecisionTreeConfig, TreeMethod};
 use ensembles_rs::utils::cross_validate;
 use ensembles_rs::utils::numeric;
 use rayon::prelude::*;
@@ -8,8 +8,8 @@ use std::collections::HashSet;
 
 use log::*;
 
-use pretty_env_logger;
 
+use std::env;
 use std::path::*;
 use std::time;
 
@@ -18,6 +18,13 @@ static DATA_DIR: &str = "../data";
 fn main() {
     pretty_env_logger::try_init_timed_custom_env("ENSEM_LOG").unwrap();
 
+    // Split finding method from cli params: approx (default), hist or exact
+    let method = match env::args().nth(1).as_deref() {
+        Some("hist") => TreeMethod::Hist,
+        Some("exact") => TreeMethod::Exact,
+        _ => TreeMethod::Approx,
+    };
+
     let data_path = Path::new(DATA_DIR);
     let start = time::SystemTime::now();
     debug!("Loading Train Data");
@@ -36,7 +43,7 @@ fn main() {
             .collect(),
     );
     let samples_count = label_data.rows();
-    let label_data = label_data.into_shape([1 as usize, samples_count]).unwrap();
+    let label_data = label_data.into_shape([1, samples_count]).unwrap();
     info!("Label data shape: {:?}", label_data.shape());
 
     info!("Load time: {}ms", start.elapsed().unwrap().as_millis());
@@ -49,6 +56,7 @@ fn main() {
         ));
         configs.insert(DecisionTreeConfig::MaxBin(300));
         configs.insert(DecisionTreeConfig::MaxDepth(10));
+        configs.insert(DecisionTreeConfig::Method(method));
         configs
     };
 
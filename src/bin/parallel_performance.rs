@@ -4,13 +4,11 @@ use ensembles_rs::tree;
 use ensembles_rs::tree::DecisionTreeConfig;
 use ensembles_rs::utils::numeric;
 use ensembles_rs::utils::set_num_threads;
-use num_cpus;
 use rayon::prelude::*;
 use std::collections::HashSet;
 
 use log::*;
 
-use pretty_env_logger;
 
 use std::env;
 use std::path::*;
@@ -50,7 +48,7 @@ fn main() {
             .collect(),
     );
     let samples_count = label_data.rows();
-    let label_data = label_data.into_shape([1 as usize, samples_count]).unwrap();
+    let label_data = label_data.into_shape([1, samples_count]).unwrap();
     info!("Label data shape: {:?}", label_data.shape());
 
     println!("Load time: {}ms", start.elapsed().unwrap().as_millis());
@@ -6,11 +6,9 @@ use ensembles_rs::tree::DecisionTreeConfig;
 use ensembles_rs::utils::numeric;
 use rayon::prelude::*;
 use std::collections::HashSet;
-use serde_json;
 
 use log::*;
 
-use pretty_env_logger;
 
 use std::path::*;
 use std::time;
@@ -42,7 +40,7 @@ fn main() {
             .collect(),
     );
     let samples_count = label_data.rows();
-    let label_data = label_data.into_shape([1 as usize, samples_count]).unwrap();
+    let label_data = label_data.into_shape([1, samples_count]).unwrap();
     info!("Label data shape: {:?}", label_data.shape());
     debug!("Loading Test Data");
     let test_data = data_frame::read_csvs(
@@ -1,7 +1,13 @@
 use crate::data_frame::*;
+use crate::flat::{FlatEnsemble, OutputTransform};
 use crate::learner::*;
+use crate::loss::*;
+use crate::ranking::*;
+use crate::tree::DecisionTree;
 use crate::utils::numeric;
+use crate::utils::random::*;
 use log::*;
+use ndarray::{ArrayView1, Axis};
 use serde::{Serialize, Deserialize};
 
 use rand::seq::SliceRandom;
@@ -10,7 +16,9 @@ use rayon::prelude::*;
 #[derive(Clone, Serialize, Deserialize)]
 pub struct GradientBoosting<L> {
     pub weak_learner: L,
-    /// Fitted learners, each learner is initially cloned from weak_learner
+    /// Fitted learners, each learner is initially cloned from weak_learner.
+    /// A softmax model fits one learner per class at each iteration, the learners of
+    /// iteration `t` are `learners[t * n_classes..(t + 1) * n_classes]` in class order.
     pub learners: Vec<L>,
     /// How each learner affect the model
     learning_rates: Vec<V>,
@@ -20,12 +28,26 @@ pub struct GradientBoosting<L> {
     pub sub_sample: f64,
     /// The initial value of the model
     init_value: V,
+    /// Seed of the subsamples and of the learners, a random one is drawn at each fit if `None`
+    #[serde(default)]
+    pub random_state: Option<u64>,
+    /// The loss the model minimizes
+    #[serde(default)]
+    pub loss: BoostingLoss,
+    /// The number of classes of a softmax model, 0 for single output models
+    #[serde(default)]
+    pub n_classes: usize,
+    /// The initial logit of each class of a softmax model
+    #[serde(default)]
+    class_init_values: Vec<V>,
 }
 
 #[derive(PartialEq, Clone, Serialize, Deserialize)]
 pub enum GBDTConfig {
     MaxIterations(usize),
     SubSample(f64),
+    RandomState(u64),
+    Loss(BoostingLoss),
 }
 
 impl<L: Learner + Clone + Sync + Send> GradientBoosting<L> {
@@ -37,6 +59,10 @@ impl<L: Learner + Clone + Sync + Send> GradientBoosting<L> {
             max_iterations: 100,
             sub_sample: 1.0,
             init_value: 0.0,
+            random_state: None,
+            loss: BoostingLoss::SquaredError,
+            n_classes: 0,
+            class_init_values: vec![],
         }
     }
 
@@ -53,74 +79,357 @@ impl<L: Learner + Clone + Sync + Send> GradientBoosting<L> {
                 SubSample(s) => {
                     boost.sub_sample = s;
                 }
+                RandomState(seed) => boost.random_state = Some(seed),
+                Loss(loss) => boost.loss = loss,
             }
         }
         boost
     }
 
-    // Returns the weak learner trained at this step
-    pub fn train_one_step(&mut self, x: &DataFrame, residuals: &DataFrame) -> L {
+    // Returns the weak learner trained at this step with the random stream `seed`
+    pub fn train_one_step(
+        &self,
+        x: &DataFrame,
+        residuals: &DataFrame,
+        w: &DataFrame,
+        seed: u64,
+    ) -> L {
         let mut learner = self.weak_learner.clone();
+        learner.set_seed(seed);
 
-        learner.fit(x, residuals);
+        learner.fit_weighted(x, residuals, w);
 
         learner
     }
 
-    /// Returns the sub sample x, residual and feature orders
-    fn choose_subsample(&self, x: &DataFrame, residual: &DataFrame) -> (DataFrame, DataFrame) {
-        let mut orders: Vec<usize> = (0..x.rows()).collect();
+    /// Returns the rows of the sub sample drawn from the stream `seed`
+    fn choose_subsample(&self, samples: usize, seed: u64) -> Vec<usize> {
+        let mut orders: Vec<usize> = (0..samples).collect();
         let sub_sample_size = (self.sub_sample * orders.len() as f64) as usize;
-        let mut rng = rand::thread_rng();
-        // select those samples with higher gradient
+        let mut rng = seeded_rng(seed);
         orders.shuffle(&mut rng);
 
-        orders.resize(sub_sample_size, 0);
+        orders.truncate(sub_sample_size);
+        orders
+    }
 
-        let mut buffer = Vec::with_capacity(sub_sample_size);
-        let mut residual_buffer = Vec::with_capacity(sub_sample_size);
-        for index in orders {
-            buffer.extend(x.row(index).iter());
-            residual_buffer.push(residual[[0, index]]);
+    /// The sum of the learners of each output, before the inverse link of the loss:
+    /// the log of the mean on a log link, the log-odds or the logits of each class
+    pub fn predict_margin(&self, df: &DataFrame) -> DataFrame {
+        if self.learners.is_empty() {
+            panic!("Model is not trained!");
+        }
+        predict_sum(&self.learners, |i| self.learning_rates[i], &self.init_values(), df)
+    }
+
+    /// Whether the loss predicts `exp` of the margin
+    fn log_link(&self) -> bool {
+        match self.loss {
+            BoostingLoss::Softmax | BoostingLoss::LambdaMart => false,
+            loss => loss.loss().log_link(),
+        }
+    }
+
+    /// The value each output of the model starts from
+    fn init_values(&self) -> Vec<V> {
+        if self.n_classes > 0 {
+            self.class_init_values.clone()
+        } else {
+            vec![self.init_value]
+        }
+    }
+
+    /// LambdaMART: each iteration fits a learner to the Newton step of the pairwise logistic
+    /// loss of the pairs of rows of each query group, weighted by their |ΔNDCG|
+    fn fit_ranking(&mut self, x: &DataFrame, y: &DataFrame, w: &DataFrame, groups: &[usize]) {
+        if y.iter().any(|y| y.is_nan() || *y < 0.0) {
+            panic!("LambdaMART loss needs non-negative relevance labels");
+        }
+        if groups.iter().sum::<usize>() != y.cols() {
+            panic!("The query groups must cover every row");
+        }
+        let samples = y.cols();
+        let mut scores = if self.learners.is_empty() {
+            // rankings don't depend on a constant
+            self.init_value = 0.0;
+            DataFrame::zeros((1, samples))
+        } else {
+            self.predict_margin(x)
+        };
+
+        info!("Start training...");
+
+        let seed = seed_or_random(self.random_state);
+        for _i in 0..self.max_iterations {
+            // every step gets its own streams, derived from the seed and the step
+            let step_seed = derive_seed(seed, self.learners.len() as u64);
+            let pairs = lambda_pairs(y, &scores, w, groups);
+            let (gradient, hessian) = lambdas(&pairs, &scores, samples);
+            let rows = self.choose_subsample(samples, derive_seed(step_seed, 0));
+            let sub_x = x.select(Axis(0), &rows);
+            let (step, step_w): (Vec<V>, Vec<V>) = rows
+                .iter()
+                .map(|i| {
+                    let hessian = hessian[*i].max(1e-6);
+                    (-gradient[*i] / hessian, hessian)
+                })
+                .unzip();
+            let model = self.train_one_step(
+                &sub_x,
+                &DataFrame::from_shape_vec((1, rows.len()), step).unwrap(),
+                &DataFrame::from_shape_vec((1, rows.len()), step_w).unwrap(),
+                derive_seed(step_seed, 1),
+            );
+            let new_scores = model.predict(x);
+
+            // Parallel line search to fine the best lr to minimize the pairwise loss
+            let (best_lr, _loss) = (1..101)
+                .into_par_iter()
+                .map(|i| {
+                    let lr = 0.01 * i as V;
+                    let loss = pairwise_loss(&pairs, |j| scores[[0, j]] + new_scores[[0, j]] * lr);
+                    (lr, loss as V)
+                })
+                .min_by(|a, b| numeric::float_cmp(a.1, b.1))
+                .unwrap();
+
+            self.learning_rates.push(best_lr);
+            scores = scores + new_scores * best_lr;
+
+            info!("lr {} at step {}.", best_lr, self.learners.len());
+            let ndcg = ndcg_at_k(y, &scores, groups, 10);
+            info!("NDCG@10: {}\n", ndcg.iter().sum::<V>() / ndcg.len().max(1) as V);
+
+            self.learners.push(model);
+        }
+    }
+
+    /// Softmax boosting: each iteration fits one learner per class, in parallel, to the
+    /// Newton step of the multinomial log-loss in the logit of that class
+    fn fit_softmax(&mut self, x: &DataFrame, y: &DataFrame, w: &DataFrame) {
+        for label in y.iter() {
+            if *label < 0.0 || label.fract() != 0.0 {
+                panic!("Softmax loss needs class index labels, found {}", label);
+            }
+        }
+        let samples = y.cols();
+        let max_class = y.iter().fold(0, |max, y| max.max(*y as usize));
+        if self.learners.is_empty() {
+            // Initialize the logits to the log of the class frequencies
+            self.n_classes = self.n_classes.max(max_class + 1).max(2);
+            let mut class_weights = vec![0.0; self.n_classes];
+            for (y, w) in y.iter().zip(w.iter()) {
+                class_weights[*y as usize] += *w as f64;
+            }
+            let total: f64 = class_weights.iter().sum();
+            self.class_init_values = class_weights
+                .iter()
+                .map(|weight| (weight / total).max(1e-6).ln() as V)
+                .collect();
+        } else if max_class >= self.n_classes {
+            panic!("The model was trained on {} classes, found class {}", self.n_classes, max_class);
+        }
+        let n_classes = self.n_classes;
+        let mut logits = if self.learners.is_empty() {
+            DataFrame::from_shape_fn((n_classes, samples), |(class, _)| {
+                self.class_init_values[class]
+            })
+        } else {
+            self.predict_margin(x)
+        };
+
+        info!("Start training...");
+
+        let seed = seed_or_random(self.random_state);
+        for _i in 0..self.max_iterations {
+            // every step gets its own streams, derived from the seed and the step
+            let step_seed = derive_seed(seed, (self.learners.len() / n_classes) as u64);
+            let mut proba = logits.clone();
+            for mut column in proba.gencolumns_mut() {
+                let mut logits = column.to_vec();
+                softmax(&mut logits);
+                column.assign(&ArrayView1::from(&logits[..]));
+            }
+            let rows = self.choose_subsample(samples, derive_seed(step_seed, 0));
+            let sub_x = x.select(Axis(0), &rows);
+            let models: Vec<L> = (0..n_classes)
+                .into_par_iter()
+                .map(|class| {
+                    let (step, step_w): (Vec<V>, Vec<V>) = rows
+                        .iter()
+                        .map(|i| {
+                            let p = proba[[class, *i]];
+                            let target = (y[[0, *i]] as usize == class) as u8 as V;
+                            let hessian = (p * (1.0 - p)).max(1e-6);
+                            ((target - p) / hessian, w[[0, *i]] * hessian)
+                        })
+                        .unzip();
+                    self.train_one_step(
+                        &sub_x,
+                        &DataFrame::from_shape_vec((1, rows.len()), step).unwrap(),
+                        &DataFrame::from_shape_vec((1, rows.len()), step_w).unwrap(),
+                        derive_seed(step_seed, class as u64 + 1),
+                    )
+                })
+                .collect();
+            let mut new_logits = DataFrame::zeros((n_classes, samples));
+            for (class, model) in models.iter().enumerate() {
+                new_logits.row_mut(class).assign(&model.predict(x).row(0));
+            }
+
+            // Parallel line search to fine the best lr to minimize the weighted loss
+            let (best_lr, _loss) = (1..101)
+                .into_par_iter()
+                .map(|i| {
+                    let lr = 0.01 * i as V;
+                    let total: f64 = (0..samples)
+                        .map(|j| {
+                            let logit = |class| logits[[class, j]] + new_logits[[class, j]] * lr;
+                            let loss = softmax_loss(logit, n_classes, y[[0, j]] as usize);
+                            (w[[0, j]] * loss) as f64
+                        })
+                        .sum();
+                    (lr, total as V)
+                })
+                .min_by(|a, b| numeric::float_cmp(a.1, b.1))
+                .unwrap();
+
+            logits = logits + new_logits * best_lr;
+            info!("lr {} at step {}.", best_lr, self.learners.len() / n_classes);
+            for model in models {
+                self.learners.push(model);
+                self.learning_rates.push(best_lr);
+            }
         }
-        (
-            DataFrame::from_shape_vec((sub_sample_size, x.cols()), buffer).unwrap(),
-            DataFrame::from_shape_vec((1, sub_sample_size), residual_buffer).unwrap(),
-        )
     }
 }
 
-impl<L: Learner + Clone + Sync + Send> Learner for GradientBoosting<L> {
-    fn fit(&mut self, x: &DataFrame, y: &DataFrame) {
+impl<L: Learner + Clone + Sync + Send> GradientBoosting<L> {
+    /// The probabilities of each class of each row of `df` as `[classes, samples]`,
+    /// only available with the logistic and softmax losses. `predict` returns the raw
+    /// log-odds, or the logits of every class.
+    pub fn predict_proba(&self, df: &DataFrame) -> DataFrame {
+        match self.loss {
+            BoostingLoss::Logistic => {
+                let margin = self.predict(df);
+                let mut proba = DataFrame::zeros((2, df.rows()));
+                for (i, margin) in margin.iter().enumerate() {
+                    let p = sigmoid(*margin);
+                    proba[[0, i]] = 1.0 - p;
+                    proba[[1, i]] = p;
+                }
+                proba
+            }
+            BoostingLoss::Softmax => {
+                let mut proba = self.predict(df);
+                for mut column in proba.gencolumns_mut() {
+                    let mut logits = column.to_vec();
+                    softmax(&mut logits);
+                    column.assign(&ArrayView1::from(&logits[..]));
+                }
+                proba
+            }
+            _ => panic!("predict_proba needs the logistic or softmax loss"),
+        }
+    }
+}
 
-//        self.init_value = y.row(0).iter().sum::<V>() / y.cols() as V;
+impl GradientBoosting<DecisionTree> {
+    /// The model as a frozen inference-only model
+    pub fn compile(&self) -> FlatEnsemble {
+        if self.learners.is_empty() {
+            panic!("Model is not trained!");
+        }
+        if self.n_classes > 0 {
+            panic!("Only single output models can be compiled");
+        }
+        let mut flat = FlatEnsemble::new(&self.learners, self.learning_rates.clone(), self.init_value);
+        if self.log_link() {
+            flat.transform = OutputTransform::Exp;
+        }
+        flat
+    }
+}
+
+impl<L: Learner + Clone + Sync + Send> Learner for GradientBoosting<L> {
+    fn fit_weighted(&mut self, x: &DataFrame, y: &DataFrame, w: &DataFrame) {
+        if self.loss == BoostingLoss::Softmax {
+            self.fit_softmax(x, y, w);
+            return;
+        }
+        if self.loss == BoostingLoss::LambdaMart {
+            panic!("LambdaMART needs the query groups of the rows, fit with fit_grouped");
+        }
+        let loss = self.loss.loss();
+        loss.check_labels(y);
         let samples = y.cols();
         let mut model_pred = if self.learners.is_empty() {
-            // Initialize F_0(x) to constant 0
-            self.init_value = 0.0;
+            // Initialize F_0(x) to the constant minimizing the loss
+            let mut targets: Vec<(V, V)> = y.iter().copied().zip(w.iter().copied()).collect();
+            self.init_value = loss.init_value(&mut targets);
             DataFrame::from_shape_vec((1, y.cols()), vec![self.init_value; samples]).unwrap()
         } else {
-            self.predict(x)
+            self.predict_margin(x)
         };
 
 
         info!("Start training...");
 
+        let seed = seed_or_random(self.random_state);
         for _i in 0..self.max_iterations {
-            let residual = y - &model_pred;
-            let (sub_x, sub_residual) = self.choose_subsample(x, &residual);
-            let model = self.train_one_step(&sub_x, &sub_residual);
+            // every step gets its own streams, derived from the seed and the step
+            let step_seed = derive_seed(seed, self.learners.len() as u64);
+            // the learner fits the Newton step with weights scaled by the hessian,
+            // or the negative gradient
+            let (step, step_w): (Vec<V>, Vec<V>) = (0..samples)
+                .map(|i| {
+                    let (y, pred) = (y[[0, i]], model_pred[[0, i]]);
+                    let gradient = loss.gradient(y, pred);
+                    match loss.hessian(y, pred) {
+                        Some(hessian) => {
+                            let hessian = hessian.max(1e-6);
+                            (-gradient / hessian, w[[0, i]] * hessian)
+                        }
+                        None => (-gradient, w[[0, i]]),
+                    }
+                })
+                .unzip();
+            let rows = self.choose_subsample(samples, derive_seed(step_seed, 0));
+            let sub_x = x.select(Axis(0), &rows);
+            let sub_step: Vec<V> = rows.iter().map(|i| step[*i]).collect();
+            let sub_w: Vec<V> = rows.iter().map(|i| step_w[*i]).collect();
+            let mut model = self.train_one_step(
+                &sub_x,
+                &DataFrame::from_shape_vec((1, rows.len()), sub_step).unwrap(),
+                &DataFrame::from_shape_vec((1, rows.len()), sub_w).unwrap(),
+                derive_seed(step_seed, 1),
+            );
+            // losses that refit leaves re-estimate them from the residuals of their rows
+            model.update_leaves(&sub_x, &|leaf_rows| {
+                let mut residuals: Vec<(V, V)> = leaf_rows
+                    .iter()
+                    .map(|row| {
+                        let i = rows[*row];
+                        (y[[0, i]] - model_pred[[0, i]], w[[0, i]])
+                    })
+                    .collect();
+                loss.leaf_value(&mut residuals)
+            });
 
-            let new_pred = model.predict(&x);
+            let new_pred = model.predict(x);
 
-            // Parallel line search to fine the best lr to minimize mse loss
+            // Parallel line search to fine the best lr to minimize the weighted loss
             let (best_lr, _r2) = (1..101)
                 .into_par_iter()
                 .map(|i| {
                     let lr = 0.01 * i as V;
-                    let pred = &model_pred + &(&new_pred * lr);
-                    let mse = numeric::mse_score(y, &pred);
-                    (lr, mse)
+                    let total: f64 = (0..samples)
+                        .map(|j| {
+                            let pred = model_pred[[0, j]] + new_pred[[0, j]] * lr;
+                            (w[[0, j]] * loss.loss(y[[0, j]], pred)) as f64
+                        })
+                        .sum();
+                    (lr, total as V)
                 })
                 .min_by(|a, b| numeric::float_cmp(a.1, b.1))
                 .unwrap();
@@ -138,17 +447,286 @@ impl<L: Learner + Clone + Sync + Send> Learner for GradientBoosting<L> {
         }
     }
 
+    /// LambdaMART fits on the query `groups`, other losses ignore them
+    fn fit_grouped(&mut self, x: &DataFrame, y: &DataFrame, w: &DataFrame, groups: &[usize]) {
+        if self.loss == BoostingLoss::LambdaMart {
+            self.fit_ranking(x, y, w, groups);
+        } else {
+            self.fit_weighted(x, y, w);
+        }
+    }
+
+    fn needs_groups(&self) -> bool {
+        self.loss == BoostingLoss::LambdaMart
+    }
+
     fn predict(&self, df: &DataFrame) -> DataFrame {
-        if self.learners.is_empty() {
-            panic!("Model is not trained!");
+        let margin = self.predict_margin(df);
+        if self.log_link() {
+            margin.mapv(V::exp)
+        } else {
+            margin
         }
-        let mut model_pred =
-            DataFrame::from_shape_vec((1, df.rows()), vec![self.init_value; df.rows()]).unwrap();
-        for i in 0..self.learners.len() {
-            let curr_pred = self.learners[i].predict(df);
-            model_pred = model_pred + curr_pred * self.learning_rates[i];
+    }
+
+    fn predict_row(&self, row: ArrayView1<V>) -> V {
+        if self.n_classes > 0 {
+            panic!("predict_row needs a single output model");
         }
+        let margin = self
+            .learners
+            .iter()
+            .zip(&self.learning_rates)
+            .fold(self.init_value, |pred, (l, lr)| pred + lr * l.predict_row(row));
+        if self.log_link() {
+            margin.exp()
+        } else {
+            margin
+        }
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.random_state = Some(seed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::boosting::*;
+    use crate::tree::*;
+    use crate::utils::cross_validate::KFold;
+    use std::collections::HashSet;
+
+    #[test]
+    fn same_seed_gives_same_boosting() {
+        let x: Vec<V> = (0..600).map(|i| ((i * 7919) % 101) as V).collect();
+        let y: Vec<V> = (0..200).map(|i| x[i * 3] - x[i * 3 + 1] + (i % 5) as V).collect();
+        let x = DataFrame::from_shape_vec((200, 3), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 200), y).unwrap();
+        let mut config = HashSet::new();
+        config.insert(DecisionTreeConfig::MaxFeatures(1));
+        // no seed of its own, the folds pass theirs
+        let boost = GradientBoosting::with_config(
+            vec![GBDTConfig::MaxIterations(5), GBDTConfig::SubSample(0.5)],
+            DecisionTree::new_with_config(config),
+        );
+        let scores = |seed: u64| {
+            KFold::with_seed(3, seed)
+                .cross_validate(boost.clone(), &x, &y, crate::utils::numeric::r2_score)
+                .validation_score
+        };
+        assert_eq!(scores(7), scores(7));
+        assert_ne!(scores(7), scores(8));
+    }
+
+    #[test]
+    fn refitted_leaves_keep_monotone_constraints() {
+        // increasing with noise an unconstrained model follows
+        let x: Vec<V> = (0..400).map(|i| (i % 100) as V).collect();
+        let y: Vec<V> = (0..400).map(|i| (i % 100) as V / 10.0 + ((i * 7919) % 13) as V).collect();
+        let x = DataFrame::from_shape_vec((400, 1), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 400), y).unwrap();
+        let grid = DataFrame::from_shape_vec((102, 1), (-1..101).map(|v| v as V).collect()).unwrap();
+        let mut config = HashSet::new();
+        config.insert(DecisionTreeConfig::MonotoneConstraints(vec![1]));
+        config.insert(DecisionTreeConfig::MaxDepth(4));
+        for loss in [BoostingLoss::Quantile(0.9), BoostingLoss::Huber(1.0), BoostingLoss::AbsoluteError] {
+            let mut boost = GradientBoosting::with_config(
+                vec![
+                    GBDTConfig::MaxIterations(20),
+                    GBDTConfig::Loss(loss),
+                    GBDTConfig::RandomState(0),
+                ],
+                DecisionTree::new_with_config(config.clone()),
+            );
+            boost.fit(&x, &y);
+            let pred = boost.predict(&grid);
+            for i in 1..102 {
+                assert!(pred[[0, i - 1]] <= pred[[0, i]], "{:?} {}", loss, i);
+            }
+        }
+    }
+
+    #[test]
+    fn robust_losses_ignore_outliers() {
+        // a ramp where every seventh label is a huge outlier, spread over the ramp
+        let x: Vec<V> = (0..400).map(|i| (i % 100) as V).collect();
+        let y: Vec<V> = (0..400)
+            .map(|i| (i % 100) as V + if i % 7 == 3 { 1000.0 } else { 0.0 })
+            .collect();
+        let x = DataFrame::from_shape_vec((400, 1), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 400), y).unwrap();
+        let clean_error = |loss: BoostingLoss| {
+            let mut config = HashSet::new();
+            config.insert(DecisionTreeConfig::MaxDepth(3));
+            let mut boost = GradientBoosting::with_config(
+                vec![
+                    GBDTConfig::MaxIterations(30),
+                    GBDTConfig::Loss(loss),
+                    GBDTConfig::RandomState(0),
+                ],
+                DecisionTree::new_with_config(config),
+            );
+            boost.fit(&x, &y);
+            let pred = boost.predict(&x);
+            let clean: Vec<usize> = (0..400).filter(|i| i % 7 != 3).collect();
+            clean.iter().map(|i| (pred[[0, *i]] - x[[*i, 0]]).abs()).sum::<V>()
+                / clean.len() as V
+        };
+        let squared = clean_error(BoostingLoss::SquaredError);
+        for loss in [BoostingLoss::AbsoluteError, BoostingLoss::Huber(5.0)] {
+            let error = clean_error(loss);
+            assert!(error < 5.0 && error < squared / 5.0, "{:?} {} {}", loss, error, squared);
+        }
+    }
 
-        model_pred
+    #[test]
+    fn logistic_loss_separates_classes() {
+        // label 1 above 60, with some flipped labels
+        let x: Vec<V> = (0..300).map(|i| (i % 100) as V).collect();
+        let y: Vec<V> = (0..300)
+            .map(|i| ((i % 100 >= 60) != (i % 23 == 0)) as u8 as V)
+            .collect();
+        let x = DataFrame::from_shape_vec((300, 1), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 300), y).unwrap();
+        let mut config = HashSet::new();
+        config.insert(DecisionTreeConfig::MaxDepth(2));
+        let mut boost = GradientBoosting::with_config(
+            vec![
+                GBDTConfig::MaxIterations(20),
+                GBDTConfig::Loss(BoostingLoss::Logistic),
+                GBDTConfig::RandomState(0),
+            ],
+            DecisionTree::new_with_config(config),
+        );
+        boost.fit(&x, &y);
+        let proba = boost.predict_proba(&x);
+        let margin = boost.predict(&x);
+        for i in 0..300 {
+            assert!((proba[[0, i]] + proba[[1, i]] - 1.0).abs() < 1e-5);
+            assert_eq!(margin[[0, i]] > 0.0, proba[[1, i]] > 0.5);
+            if i % 100 < 55 {
+                assert!(proba[[1, i]] < 0.5, "{} {}", i, proba[[1, i]]);
+            } else if i % 100 >= 65 {
+                assert!(proba[[1, i]] > 0.5, "{} {}", i, proba[[1, i]]);
+            }
+        }
+    }
+
+    #[test]
+    fn log_link_losses_predict_positive_means() {
+        // counts with mean 0.5 below x = 50 and 4 above, zeros included
+        let x: Vec<V> = (0..400).map(|i| (i % 100) as V).collect();
+        let counts: Vec<V> = (0..400)
+            .map(|i| if i % 100 < 50 { (i / 100 % 2) as V } else { (i / 100 % 2 * 2 + 3) as V })
+            .collect();
+        let x = DataFrame::from_shape_vec((400, 1), x).unwrap();
+        let counts = DataFrame::from_shape_vec((1, 400), counts).unwrap();
+        let mut config = HashSet::new();
+        config.insert(DecisionTreeConfig::MaxDepth(1));
+        for loss in [BoostingLoss::Poisson, BoostingLoss::Gamma, BoostingLoss::Tweedie(1.5)] {
+            // Gamma needs positive labels
+            let y = if loss == BoostingLoss::Gamma { counts.mapv(|c| c + 1.0) } else { counts.clone() };
+            let shift = if loss == BoostingLoss::Gamma { 1.0 } else { 0.0 };
+            let mut boost = GradientBoosting::with_config(
+                vec![
+                    GBDTConfig::MaxIterations(30),
+                    GBDTConfig::Loss(loss),
+                    GBDTConfig::RandomState(0),
+                ],
+                DecisionTree::new_with_config(config.clone()),
+            );
+            boost.fit(&x, &y);
+            // the model starts from the log of the mean
+            assert!((boost.init_value.exp() - (2.25 + shift)).abs() < 0.1, "{:?}", loss);
+            let pred = boost.predict(&x);
+            for i in 0..400 {
+                let expected = if i % 100 < 50 { 0.5 } else { 4.0 } + shift;
+                assert!((pred[[0, i]] - expected).abs() < 0.2, "{:?} {} {}", loss, i, pred[[0, i]]);
+            }
+            assert!((boost.predict_row(x.row(70)) - pred[[0, 70]]).abs() < 1e-4);
+            assert!((boost.predict_margin(&x)[[0, 70]] - pred[[0, 70]].ln()).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn lambdamart_ranks_query_groups() {
+        // 30 queries of 10 rows, relevance grows with feature 0 up to a shift of each query
+        let relevance: Vec<V> = (0..300).map(|i| ((i * 7919) % 4) as V).collect();
+        let x: Vec<V> = (0..300)
+            .flat_map(|i| {
+                let noise = ((i * 31) % 10) as V / 25.0;
+                vec![relevance[i] + noise + (i / 10 % 3) as V, ((i * 13) % 7) as V]
+            })
+            .collect();
+        let x = DataFrame::from_shape_vec((300, 2), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 300), relevance).unwrap();
+        let groups = vec![10; 30];
+        let mut config = HashSet::new();
+        config.insert(DecisionTreeConfig::MaxDepth(3));
+        let boost = GradientBoosting::with_config(
+            vec![
+                GBDTConfig::MaxIterations(20),
+                GBDTConfig::Loss(BoostingLoss::LambdaMart),
+                GBDTConfig::RandomState(0),
+            ],
+            DecisionTree::new_with_config(config),
+        );
+        let mean_ndcg = |y: &DataFrame, pred: &DataFrame, groups: &[usize]| {
+            let ndcg = ndcg_at_k(y, pred, groups, 5);
+            ndcg.iter().sum::<V>() / ndcg.len() as V
+        };
+
+        let mut model = boost.clone();
+        model.fit_grouped(&x, &y, &DataFrame::ones((1, 300)), &groups);
+        let unranked = mean_ndcg(&y, &x.column(1).insert_axis(Axis(0)).to_owned(), &groups);
+        let ndcg = mean_ndcg(&y, &model.predict(&x), &groups);
+        assert!(ndcg > 0.95 && ndcg > unranked, "{} {}", ndcg, unranked);
+
+        // folds keep the queries whole
+        let metric = |y: &DataFrame, pred: &DataFrame, groups: &[usize]| {
+            assert_eq!(y.cols(), groups.iter().sum::<usize>());
+            assert!(groups.iter().all(|size| *size == 10));
+            mean_ndcg(y, pred, groups)
+        };
+        let scores = KFold::with_seed(3, 0).cross_validate_grouped(boost.clone(), &x, &y, &groups, metric);
+        for score in scores.validation_score {
+            assert!(score > 0.9, "{}", score);
+        }
+        // rows without their groups can't be ranked
+        let ungrouped = std::panic::catch_unwind(|| {
+            KFold::new(3).cross_validate(boost, &x, &y, crate::utils::numeric::r2_score)
+        });
+        assert!(ungrouped.is_err());
+    }
+
+    #[test]
+    fn softmax_loss_separates_three_classes() {
+        // classes 0, 1 and 2 over ranges of x, more rows than a prediction block
+        let x: Vec<V> = (0..600).map(|i| (i % 100) as V).collect();
+        let y: Vec<V> = (0..600).map(|i| ((i % 100) / 34) as V).collect();
+        let x = DataFrame::from_shape_vec((600, 1), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 600), y).unwrap();
+        let mut config = HashSet::new();
+        config.insert(DecisionTreeConfig::MaxDepth(2));
+        let mut boost = GradientBoosting::with_config(
+            vec![
+                GBDTConfig::MaxIterations(10),
+                GBDTConfig::Loss(BoostingLoss::Softmax),
+                GBDTConfig::RandomState(0),
+            ],
+            DecisionTree::new_with_config(config),
+        );
+        boost.fit(&x, &y);
+        assert_eq!(3, boost.n_classes);
+        assert_eq!(30, boost.learners.len());
+        let proba = boost.predict_proba(&x);
+        assert_eq!(&[3, 600], proba.shape());
+        for i in 0..600 {
+            let column = proba.column(i);
+            assert!((column.sum() - 1.0).abs() < 1e-5);
+            let best = (0..3).max_by(|a, b| column[*a].total_cmp(&column[*b])).unwrap();
+            assert_eq!(y[[0, i]] as usize, best, "{} {:?}", i, column);
+        }
     }
 }
@@ -0,0 +1,166 @@
+use crate::criterion::SplitCriterion;
+use crate::data_frame::*;
+use crate::histogram::{score_split, NodeStats};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::ops::Range;
+
+/// A set of category codes stored as a bitset
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CategorySet {
+    words: Vec<u64>,
+}
+
+impl CategorySet {
+    pub fn from_categories(categories: &[usize]) -> Self {
+        let mut set = Self::default();
+        for category in categories {
+            set.insert(*category);
+        }
+        set
+    }
+
+    pub fn insert(&mut self, category: usize) {
+        let word = category / 64;
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1u64 << (category % 64);
+    }
+
+    #[inline]
+    pub fn contains(&self, category: usize) -> bool {
+        self.words
+            .get(category / 64)
+            .is_some_and(|word| word & (1u64 << (category % 64)) != 0)
+    }
+
+    /// Whether the feature value `val` is one of the categories,
+    /// values that are not category codes never are
+    #[inline]
+    pub fn contains_value(&self, val: V) -> bool {
+        val >= 0.0 && val.fract() == 0.0 && self.contains(val as usize)
+    }
+}
+
+/// Label statistics of the samples of a node holding one category
+#[derive(Debug, Clone)]
+pub struct CategoryStats {
+    pub category: usize,
+    pub stats: NodeStats,
+}
+
+/// The best subset of categories to send to the left child.
+///
+/// With `one_vs_rest` every category is tried alone against the others, otherwise
+/// categories are sorted by the mean of their labels and every prefix is tried, which
+/// finds the best subset for regression and binary classification.
+/// Missing values go to the side that scores lower, see `score_split`.
+/// Returns the left categories, the proxy impurity and whether missing values go left.
+pub fn find_category_split(
+    criterion: &dyn SplitCriterion,
+    mut categories: Vec<CategoryStats>,
+    missing: &NodeStats,
+    total: &NodeStats,
+    min_samples_leaf: usize,
+    one_vs_rest: bool,
+) -> Option<(CategorySet, V, bool)> {
+    categories.retain(|c| c.stats.count > 0);
+    // (left categories, impurity, whether missing values go left)
+    let mut best: Option<(Vec<usize>, V, bool)> = None;
+    if one_vs_rest {
+        for category in &categories {
+            if let Some((impurity, default_left)) =
+                score_split(criterion, total, &category.stats, missing, min_samples_leaf, 0)
+            {
+                if best.as_ref().is_none_or(|b| impurity < b.1) {
+                    best = Some((vec![category.category], impurity, default_left));
+                }
+            }
+        }
+    } else {
+        sort_by_mean(&mut categories);
+        let mut left = NodeStats::new(total.classes.len());
+        for (i, category) in categories.iter().enumerate() {
+            left.merge(&category.stats);
+            // sending every category left only splits off the missing values
+            if i + 1 == categories.len() && missing.count == 0 {
+                break;
+            }
+            if let Some((impurity, default_left)) =
+                score_split(criterion, total, &left, missing, min_samples_leaf, 0)
+            {
+                if best.as_ref().is_none_or(|b| impurity < b.1) {
+                    let left_categories = categories[..=i].iter().map(|c| c.category).collect();
+                    best = Some((left_categories, impurity, default_left));
+                }
+            }
+        }
+    }
+    best.map(|(left, impurity, default_left)| {
+        (CategorySet::from_categories(&left), impurity, default_left)
+    })
+}
+
+/// The number of samples of `order` with a present value of the categorical `feature`,
+/// sorted first, and the contiguous range of `order` of each category
+pub fn category_ranges(
+    df: &DataFrame,
+    feature: usize,
+    order: &[usize],
+) -> (usize, Vec<(usize, Range<usize>)>) {
+    let present = order.partition_point(|i| !df[[*i, feature]].is_nan());
+    let mut ranges: Vec<(usize, Range<usize>)> = vec![];
+    for (pos, index) in order[..present].iter().enumerate() {
+        let category = df[[*index, feature]] as usize;
+        match ranges.last_mut() {
+            Some((last, range)) if *last == category => range.end = pos + 1,
+            _ => ranges.push((category, pos..pos + 1)),
+        }
+    }
+    (present, ranges)
+}
+
+/// Sorts categories by the mean of their labels
+pub fn sort_by_mean(categories: &mut [CategoryStats]) {
+    categories.sort_by(|a, b| {
+        a.stats
+            .mean()
+            .partial_cmp(&b.stats.mean())
+            .unwrap_or(Ordering::Equal)
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use crate::categorical::*;
+    use crate::criterion::Mse;
+
+    #[test]
+    fn groups_categories_by_mean() {
+        // categories 0 and 2 have low labels, 1 and 3 high ones
+        let categories: Vec<CategoryStats> = [1.0, 9.0, 2.0, 8.0]
+            .iter()
+            .enumerate()
+            .map(|(category, y)| {
+                let mut stats = NodeStats::new(0);
+                for _ in 0..5 {
+                    stats.add(*y, 1.0);
+                }
+                CategoryStats { category, stats }
+            })
+            .collect();
+        let mut total = NodeStats::new(0);
+        for category in &categories {
+            total.merge(&category.stats);
+        }
+        let (left, _, _) =
+            find_category_split(&Mse, categories, &NodeStats::new(0), &total, 1, false)
+                .unwrap();
+        assert_eq!(CategorySet::from_categories(&[0, 2]), left);
+        assert!(left.contains_value(2.0));
+        assert!(!left.contains_value(1.0));
+        assert!(!left.contains_value(0.5));
+        assert!(!left.contains_value(100.0));
+    }
+}
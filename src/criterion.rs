@@ -0,0 +1,404 @@
+use crate::data_frame::*;
+use crate::histogram::NodeStats;
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+/// The impurity a split of `DecisionTree` minimizes.
+/// Impurities are sums over the samples of a node, so the impurity of a split
+/// is the sum of its children's impurities.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+pub enum Criterion {
+    /// Regression: the weighted square sum of labels minus their mean
+    #[default]
+    Mse,
+    /// Regression: square error with Friedman's improvement score,
+    /// the weighted square difference of the children's means
+    FriedmanMse,
+    /// Regression: the weighted absolute deviation of labels from their median,
+    /// leaves predict the median
+    Mae,
+    /// Regression of non-negative counts: the half Poisson deviance of labels from their mean
+    Poisson,
+    /// Classification: Gini impurity of the class weights
+    Gini,
+    /// Classification: Shannon entropy of the class weights
+    Entropy,
+}
+
+impl Criterion {
+    /// Labels are class indexes and leaves predict classes
+    pub fn is_classification(&self) -> bool {
+        match self {
+            Criterion::Mse | Criterion::FriedmanMse | Criterion::Mae | Criterion::Poisson => false,
+            Criterion::Gini | Criterion::Entropy => true,
+        }
+    }
+
+    /// The node impurity and leaf values of the criterion
+    pub fn node_criterion(&self) -> &'static dyn NodeCriterion {
+        match self {
+            Criterion::Mse => &Mse,
+            Criterion::FriedmanMse => &FriedmanMse,
+            Criterion::Mae => &Mae,
+            Criterion::Poisson => &Poisson,
+            Criterion::Gini => &Gini,
+            Criterion::Entropy => &Entropy,
+        }
+    }
+
+    /// How the criterion scores splits
+    pub fn scoring(&self) -> Scoring {
+        match self {
+            Criterion::Mse => Scoring::Proxy(&Mse),
+            Criterion::FriedmanMse => Scoring::Proxy(&FriedmanMse),
+            Criterion::Mae => Scoring::Ordered(&Mae),
+            Criterion::Poisson => Scoring::Proxy(&Poisson),
+            Criterion::Gini => Scoring::Proxy(&Gini),
+            Criterion::Entropy => Scoring::Proxy(&Entropy),
+        }
+    }
+}
+
+/// How a criterion scores the splits of a node
+#[derive(Clone, Copy)]
+pub enum Scoring {
+    /// From the label statistics of the children
+    Proxy(&'static dyn SplitCriterion),
+    /// From the impurities of the labels scanned in the order splits are searched in
+    Ordered(&'static dyn OrderedCriterion),
+}
+
+/// Scores the nodes of a `DecisionTree` and the values of its leaves.
+/// Criteria score splits either from the children's label statistics, see `SplitCriterion`,
+/// or from order statistics of the labels that `NodeStats` cannot hold, see
+/// `OrderedCriterion`.
+pub trait NodeCriterion: Sync {
+    /// The impurity of a node, `targets` are the `(label, weight)` of its samples
+    fn node_impurity(&self, stats: &NodeStats, targets: &[(V, V)]) -> V;
+
+    /// The prediction of a leaf holding `targets`
+    fn leaf_value(&self, stats: &NodeStats, _targets: &[(V, V)]) -> V {
+        stats.mean()
+    }
+}
+
+/// Scores splits with a proxy impurity computed from the children's label statistics,
+/// it may differ from the real impurity of the split by a term that is the same for every
+/// split of a node.
+pub trait SplitCriterion: NodeCriterion {
+    /// The impurity of a split up to a term shared by every split of the node,
+    /// empty children count for nothing
+    fn proxy_impurity(&self, left: &NodeStats, right: &NodeStats) -> V;
+}
+
+/// Scores splits from the impurities of every prefix of the labels sorted by feature value
+pub trait OrderedCriterion: NodeCriterion {
+    /// `impurities[i]` is the impurity of `targets[..i]`
+    fn prefix_impurities(&self, targets: &[(V, V)]) -> Vec<V>;
+}
+
+/// Sum of squared errors
+pub struct Mse;
+
+impl NodeCriterion for Mse {
+    fn node_impurity(&self, stats: &NodeStats, _targets: &[(V, V)]) -> V {
+        stats.sse()
+    }
+}
+
+impl SplitCriterion for Mse {
+    fn proxy_impurity(&self, left: &NodeStats, right: &NodeStats) -> V {
+        left.sse() + right.sse()
+    }
+}
+
+/// Sum of squared errors, splits are scored by Friedman's improvement
+/// `w_l * w_r / (w_l + w_r) * (mean_l - mean_r)^2`
+pub struct FriedmanMse;
+
+impl NodeCriterion for FriedmanMse {
+    fn node_impurity(&self, stats: &NodeStats, _targets: &[(V, V)]) -> V {
+        stats.sse()
+    }
+}
+
+impl SplitCriterion for FriedmanMse {
+    fn proxy_impurity(&self, left: &NodeStats, right: &NodeStats) -> V {
+        if left.weight <= 0.0 || right.weight <= 0.0 {
+            return 0.0;
+        }
+        let diff = left.sum / left.weight - right.sum / right.weight;
+        -(left.weight * right.weight / (left.weight + right.weight) * diff * diff) as V
+    }
+}
+
+/// Sum of absolute deviations from the weighted median
+pub struct Mae;
+
+impl NodeCriterion for Mae {
+    fn node_impurity(&self, _stats: &NodeStats, targets: &[(V, V)]) -> V {
+        self.prefix_impurities(targets).pop().unwrap_or(0.0)
+    }
+
+    fn leaf_value(&self, _stats: &NodeStats, targets: &[(V, V)]) -> V {
+        let mut median = RunningMedian::default();
+        for (y, weight) in targets {
+            median.add(*y, *weight);
+        }
+        median.median()
+    }
+}
+
+impl OrderedCriterion for Mae {
+    fn prefix_impurities(&self, targets: &[(V, V)]) -> Vec<V> {
+        let mut median = RunningMedian::default();
+        let mut impurities = Vec::with_capacity(targets.len() + 1);
+        impurities.push(0.0);
+        for (y, weight) in targets {
+            median.add(*y, *weight);
+            impurities.push(median.absolute_deviation());
+        }
+        impurities
+    }
+}
+
+/// Half Poisson deviance `sum(w * (y * ln(y / mean) - y + mean))`, labels must not be negative.
+/// A child whose labels are all zero would predict a zero rate, so it is never split off.
+pub struct Poisson;
+
+impl NodeCriterion for Poisson {
+    fn node_impurity(&self, stats: &NodeStats, targets: &[(V, V)]) -> V {
+        if stats.sum <= 0.0 {
+            return 0.0;
+        }
+        let y_log_y: f64 = targets
+            .iter()
+            .filter(|(y, _)| *y > 0.0)
+            .map(|(y, weight)| *weight as f64 * *y as f64 * (*y as f64).ln())
+            .sum();
+        (y_log_y - stats.sum * (stats.sum / stats.weight).ln()).max(0.0) as V
+    }
+}
+
+impl SplitCriterion for Poisson {
+    /// The `y * ln(y)` terms are the same for every split and the `mean - y` terms sum to 0,
+    /// the remaining terms are taken relative to the node's to keep the proxy small
+    fn proxy_impurity(&self, left: &NodeStats, right: &NodeStats) -> V {
+        let term = |sum: f64, weight: f64| {
+            if weight <= 0.0 {
+                0.0
+            } else if sum <= 0.0 {
+                f64::INFINITY
+            } else {
+                -sum * (sum / weight).ln()
+            }
+        };
+        let node = term(left.sum + right.sum, left.weight + right.weight);
+        if node.is_infinite() {
+            return V::INFINITY;
+        }
+        (term(left.sum, left.weight) + term(right.sum, right.weight) - node) as V
+    }
+}
+
+/// Gini impurity of the class weights
+pub struct Gini;
+
+impl Gini {
+    fn impurity(stats: &NodeStats) -> V {
+        if stats.weight <= 0.0 {
+            return 0.0;
+        }
+        let square_sum: f64 = stats.classes.iter().map(|w| w * w).sum();
+        (stats.weight - square_sum / stats.weight) as V
+    }
+}
+
+impl NodeCriterion for Gini {
+    fn node_impurity(&self, stats: &NodeStats, _targets: &[(V, V)]) -> V {
+        Self::impurity(stats)
+    }
+
+    fn leaf_value(&self, stats: &NodeStats, _targets: &[(V, V)]) -> V {
+        stats.majority_class() as V
+    }
+}
+
+impl SplitCriterion for Gini {
+    fn proxy_impurity(&self, left: &NodeStats, right: &NodeStats) -> V {
+        Self::impurity(left) + Self::impurity(right)
+    }
+}
+
+/// Shannon entropy of the class weights
+pub struct Entropy;
+
+impl Entropy {
+    fn impurity(stats: &NodeStats) -> V {
+        if stats.weight <= 0.0 {
+            return 0.0;
+        }
+        let entropy: f64 = stats
+            .classes
+            .iter()
+            .filter(|w| **w > 0.0)
+            .map(|w| -w * (w / stats.weight).ln())
+            .sum();
+        entropy as V
+    }
+}
+
+impl NodeCriterion for Entropy {
+    fn node_impurity(&self, stats: &NodeStats, _targets: &[(V, V)]) -> V {
+        Self::impurity(stats)
+    }
+
+    fn leaf_value(&self, stats: &NodeStats, _targets: &[(V, V)]) -> V {
+        stats.majority_class() as V
+    }
+}
+
+impl SplitCriterion for Entropy {
+    fn proxy_impurity(&self, left: &NodeStats, right: &NodeStats) -> V {
+        Self::impurity(left) + Self::impurity(right)
+    }
+}
+
+/// A weighted label ordered by value
+#[derive(Debug, Clone, Copy)]
+struct Weighted(V, V);
+
+impl PartialEq for Weighted {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Weighted {}
+
+impl PartialOrd for Weighted {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Weighted {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Weighted median of a growing set of labels, kept as the largest label of a lower
+/// half holding at least half of the weight and the smallest label of an upper half
+#[derive(Debug, Default)]
+struct RunningMedian {
+    lower: BinaryHeap<Weighted>,
+    upper: BinaryHeap<Reverse<Weighted>>,
+    lower_weight: f64,
+    lower_sum: f64,
+    upper_weight: f64,
+    upper_sum: f64,
+}
+
+impl RunningMedian {
+    fn add(&mut self, y: V, weight: V) {
+        if self.lower.peek().is_none_or(|top| y <= top.0) {
+            self.push_lower(Weighted(y, weight));
+        } else {
+            self.push_upper(Weighted(y, weight));
+        }
+        // the lower half must hold at least half of the weight without its top
+        let half = (self.lower_weight + self.upper_weight) / 2.0;
+        while self.lower_weight < half {
+            let Reverse(label) = self.upper.pop().unwrap();
+            self.upper_weight -= label.1 as f64;
+            self.upper_sum -= label.1 as f64 * label.0 as f64;
+            self.push_lower(label);
+        }
+        while let Some(top) = self.lower.peek() {
+            if self.lower_weight - (top.1 as f64) < half {
+                break;
+            }
+            let label = self.lower.pop().unwrap();
+            self.lower_weight -= label.1 as f64;
+            self.lower_sum -= label.1 as f64 * label.0 as f64;
+            self.push_upper(label);
+        }
+    }
+
+    fn push_lower(&mut self, label: Weighted) {
+        self.lower_weight += label.1 as f64;
+        self.lower_sum += label.1 as f64 * label.0 as f64;
+        self.lower.push(label);
+    }
+
+    fn push_upper(&mut self, label: Weighted) {
+        self.upper_weight += label.1 as f64;
+        self.upper_sum += label.1 as f64 * label.0 as f64;
+        self.upper.push(Reverse(label));
+    }
+
+    fn median(&self) -> V {
+        self.lower.peek().map_or(0.0, |top| top.0)
+    }
+
+    /// Weighted sum of absolute deviations from the median
+    fn absolute_deviation(&self) -> V {
+        let median = self.median() as f64;
+        (median * self.lower_weight - self.lower_sum + self.upper_sum - median * self.upper_weight)
+            .max(0.0) as V
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::criterion::*;
+
+    #[test]
+    fn running_median_matches_sorting() {
+        let targets: Vec<(V, V)> = (0..50)
+            .map(|i| (((i * 37) % 23) as V, (1 + i % 3) as V))
+            .collect();
+        let impurities = Mae.prefix_impurities(&targets);
+        for end in 1..=targets.len() {
+            let mut prefix = targets[..end].to_vec();
+            prefix.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let total: V = prefix.iter().map(|t| t.1).sum();
+            let mut seen = 0.0;
+            let median = prefix
+                .iter()
+                .find(|t| {
+                    seen += t.1;
+                    seen >= total / 2.0
+                })
+                .unwrap()
+                .0;
+            let deviation: V = prefix.iter().map(|t| t.1 * (t.0 - median).abs()).sum();
+            assert!((impurities[end] - deviation).abs() < 1e-3, "{}", end);
+        }
+    }
+
+    #[test]
+    fn poisson_proxy_ranks_like_deviance() {
+        let targets = [(1.0, 1.0), (2.0, 1.0), (4.0, 2.0), (7.0, 1.0)];
+        let stats = |targets: &[(V, V)]| {
+            let mut stats = NodeStats::new(0);
+            for (y, w) in targets {
+                stats.add(*y, *w);
+            }
+            stats
+        };
+        // proxy and real impurity differ by the same term for every split
+        let offsets: Vec<V> = (1..targets.len())
+            .map(|pos| {
+                let (left, right) = targets.split_at(pos);
+                Poisson.node_impurity(&stats(left), left) + Poisson.node_impurity(&stats(right), right)
+                    - Poisson.proxy_impurity(&stats(left), &stats(right))
+            })
+            .collect();
+        for offset in &offsets {
+            assert!((offset - offsets[0]).abs() < 1e-5);
+        }
+    }
+}
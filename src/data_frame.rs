@@ -42,20 +42,19 @@ pub fn df_from_data(buf: Vec<Vec<V>>) -> DataFrame {
     } else {
         let shape = (buf.len(), buf[0].len());
         let flatten = buf.into_iter().flatten().collect();
-        let arr = Array2::from_shape_vec(shape, flatten).unwrap();
-        arr
+        Array2::from_shape_vec(shape, flatten).unwrap()
     }
 }
 
 #[allow(dead_code)]
-pub fn sort_f64_vec(v: &mut Vec<V>) {
+pub fn sort_f64_vec(v: &mut [V]) {
     use std::cmp::Ordering;
     v.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
 }
 
 pub fn read_csvs(file_paths: Vec<PathBuf>) -> DataFrame {
     let mut data_frame = Vec::new();
-    let frames: Vec<Vec<Vec<V>>> = file_paths.par_iter().map(|name| load_csv(name)).collect();
+    let frames: Vec<Vec<Vec<V>>> = file_paths.par_iter().map(load_csv).collect();
 
     for frame in frames {
         data_frame.extend(frame);
@@ -69,18 +68,27 @@ pub fn save_csv(df: &DataFrame, file_path: PathBuf, headers: &[&str]) {
     let file = File::create(file_path).unwrap();
     let mut writer = BufWriter::new(file);
     writer
-        .write(headers.join(",").as_bytes())
+        .write_all(headers.join(",").as_bytes())
         .expect("Unable to write data");
-    writer.write(b"\n").expect("Unable to write data");
+    writer.write_all(b"\n").expect("Unable to write data");
     for i in 0..df.rows() {
         let row: Vec<String> = (0..df.cols())
-            .into_iter()
             .map(|c| df[[i, c]].to_string())
             .collect();
         writer
-            .write(row.join(",").as_bytes())
+            .write_all(row.join(",").as_bytes())
             .expect("Unable to write data");
-        writer.write(b"\n").expect("Unable to write data");
+        writer.write_all(b"\n").expect("Unable to write data");
+    }
+}
+
+/// Parses a CSV cell, empty and `NA` cells are missing values read as NaN
+fn parse_value(cell: &str) -> V {
+    match cell.trim() {
+        "" | "NA" | "N/A" | "na" => V::NAN,
+        value => value
+            .parse::<V>()
+            .unwrap_or_else(|e| panic!("couldn't parse {:?}: {}", value, e)),
     }
 }
 
@@ -98,7 +106,7 @@ fn load_csv(file_path: &PathBuf) -> Vec<Vec<V>> {
         data_frame.push(
             record
                 .iter()
-                .map(|e: &str| e.parse::<V>().unwrap())
+                .map(parse_value)
                 .collect(),
         );
     }
@@ -0,0 +1,347 @@
+use crate::categorical::CategorySet;
+use crate::data_frame::*;
+use crate::learner::PREDICT_BLOCK;
+use crate::random_forest::RandomForest;
+use crate::tree::*;
+use ndarray::ArrayView1;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Missing values of the node's feature go to the left child
+const DEFAULT_LEFT: u32 = 1 << 31;
+/// The node splits by a category set, its `param` holds the bits of the set's index
+const CATEGORICAL: u32 = 1 << 30;
+const FEATURE_MASK: u32 = CATEGORICAL - 1;
+
+/// An inference-only tree stored as arrays, 12 bytes per node.
+/// Nodes are laid out breadth first with siblings side by side, the right child
+/// of a stem directly follows its left one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlatTree {
+    /// The split feature of each stem, with the `DEFAULT_LEFT` and `CATEGORICAL` flags
+    pub feature: Vec<u32>,
+    /// The threshold of each stem, or the value of each leaf
+    pub param: Vec<V>,
+    /// The left child of each stem, 0 for leaves
+    pub left: Vec<u32>,
+    /// The threshold of each stem as a bin of its feature, only for quantized ensembles
+    pub bin: Vec<u16>,
+    /// The left categories of categorical stems
+    pub categories: Vec<CategorySet>,
+}
+
+impl FlatTree {
+    pub fn from_tree(tree: &DecisionTree) -> Self {
+        if tree.nodes.is_empty() {
+            panic!("Model is not trained!");
+        }
+        let mut flat = FlatTree {
+            feature: vec![0; tree.nodes.len()],
+            param: vec![0.0; tree.nodes.len()],
+            left: vec![0; tree.nodes.len()],
+            bin: vec![],
+            categories: vec![],
+        };
+        // (node in the tree, position in the flat tree)
+        let mut queue = VecDeque::from(vec![(0, 0)]);
+        let mut next = 1;
+        while let Some((index, pos)) = queue.pop_front() {
+            let node = &tree.nodes[index];
+            let (feature, left, right, default_left) = match &node.info {
+                NodeInfo::Leaf => {
+                    flat.param[pos] = node.value;
+                    continue;
+                }
+                NodeInfo::Stem {
+                    feature,
+                    param,
+                    left,
+                    right,
+                    default_left,
+                } => {
+                    flat.param[pos] = *param;
+                    (*feature, *left, *right, *default_left)
+                }
+                NodeInfo::CategoryStem {
+                    feature,
+                    categories,
+                    left,
+                    right,
+                    default_left,
+                } => {
+                    flat.param[pos] = V::from_bits(flat.categories.len() as u32);
+                    flat.categories.push(categories.clone());
+                    (*feature | CATEGORICAL as usize, *left, *right, *default_left)
+                }
+            };
+            flat.feature[pos] = feature as u32 | if default_left { DEFAULT_LEFT } else { 0 };
+            flat.left[pos] = next as u32;
+            queue.push_back((left, next));
+            queue.push_back((right, next + 1));
+            next += 2;
+        }
+        flat
+    }
+
+    /// The position of the leaf `row` falls into, `bins` are the row's bins in a
+    /// quantized ensemble
+    #[inline]
+    fn leaf_of(&self, row: ArrayView1<V>, bins: Option<&[u16]>) -> usize {
+        let mut node = 0;
+        loop {
+            let left = self.left[node] as usize;
+            if left == 0 {
+                return node;
+            }
+            let packed = self.feature[node];
+            let feature = (packed & FEATURE_MASK) as usize;
+            let val = row[feature];
+            let goes_left = if val.is_nan() {
+                packed & DEFAULT_LEFT != 0
+            } else if packed & CATEGORICAL != 0 {
+                self.categories[self.param[node].to_bits() as usize].contains_value(val)
+            } else if let Some(bins) = bins {
+                bins[feature] <= self.bin[node]
+            } else {
+                val <= self.param[node]
+            };
+            node = left + !goes_left as usize;
+        }
+    }
+
+    pub fn predict_row(&self, row: ArrayView1<V>) -> V {
+        self.param[self.leaf_of(row, None)]
+    }
+}
+
+/// How the sum of the trees of an ensemble becomes its prediction
+#[derive(Debug, Default, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum OutputTransform {
+    #[default]
+    Identity,
+    /// The sum is the log of the prediction, as for losses on a log link
+    Exp,
+}
+
+impl OutputTransform {
+    #[inline]
+    pub fn apply(self, sum: V) -> V {
+        match self {
+            OutputTransform::Identity => sum,
+            OutputTransform::Exp => sum.exp(),
+        }
+    }
+}
+
+/// A weighted sum of flat trees: `transform(init + sum(weights[i] * trees[i]))`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlatEnsemble {
+    pub trees: Vec<FlatTree>,
+    pub weights: Vec<V>,
+    pub init: V,
+    /// The sorted thresholds of each feature once quantized, empty otherwise
+    pub cuts: Vec<Vec<V>>,
+    #[serde(default)]
+    pub transform: OutputTransform,
+}
+
+impl FlatEnsemble {
+    pub fn new(trees: &[DecisionTree], weights: Vec<V>, init: V) -> Self {
+        assert_eq!(trees.len(), weights.len());
+        Self {
+            trees: trees.par_iter().map(FlatTree::from_tree).collect(),
+            weights,
+            init,
+            cuts: vec![],
+            transform: OutputTransform::Identity,
+        }
+    }
+
+    /// Replaces thresholds by bins of their feature: the thresholds of every tree are
+    /// gathered per feature, rows are bucketed once by them and stems compare bins
+    pub fn quantize(&mut self) {
+        let n_features = self
+            .trees
+            .iter()
+            .flat_map(|tree| tree.feature.iter().zip(&tree.left))
+            .filter(|(_, left)| **left != 0)
+            .map(|(packed, _)| (packed & FEATURE_MASK) as usize + 1)
+            .max()
+            .unwrap_or(0);
+        let mut cuts = vec![vec![]; n_features];
+        for tree in &self.trees {
+            for node in 0..tree.left.len() {
+                if tree.left[node] != 0 && tree.feature[node] & CATEGORICAL == 0 {
+                    cuts[(tree.feature[node] & FEATURE_MASK) as usize].push(tree.param[node]);
+                }
+            }
+        }
+        for feature_cuts in &mut cuts {
+            feature_cuts.sort_by(|a, b| a.total_cmp(b));
+            feature_cuts.dedup();
+            if feature_cuts.len() > u16::MAX as usize {
+                panic!("A feature has too many thresholds to quantize");
+            }
+        }
+        for tree in &mut self.trees {
+            tree.bin = (0..tree.left.len())
+                .map(|node| {
+                    if tree.left[node] == 0 || tree.feature[node] & CATEGORICAL != 0 {
+                        return 0;
+                    }
+                    let feature_cuts = &cuts[(tree.feature[node] & FEATURE_MASK) as usize];
+                    feature_cuts.partition_point(|cut| *cut < tree.param[node]) as u16
+                })
+                .collect();
+        }
+        self.cuts = cuts;
+    }
+
+    /// The bin of each feature of `row`: the first threshold the value is below,
+    /// so `value <= threshold` exactly when `bin <= threshold's bin`
+    #[inline]
+    fn bin_row(&self, row: ArrayView1<V>, bins: &mut [u16]) {
+        for (feature, cuts) in self.cuts.iter().enumerate() {
+            bins[feature] = cuts.partition_point(|cut| *cut < row[feature]) as u16;
+        }
+    }
+
+    pub fn predict_row(&self, row: ArrayView1<V>) -> V {
+        let mut bins = vec![0; self.cuts.len()];
+        self.bin_row(row, &mut bins);
+        let bins = if self.cuts.is_empty() { None } else { Some(&bins[..]) };
+        let sum = self
+            .trees
+            .iter()
+            .zip(&self.weights)
+            .fold(self.init, |pred, (tree, weight)| {
+                pred + weight * tree.param[tree.leaf_of(row, bins)]
+            });
+        self.transform.apply(sum)
+    }
+
+    /// Predicts blocks of rows in parallel, every tree goes through a block before the next
+    pub fn predict(&self, df: &DataFrame) -> DataFrame {
+        let n_features = self.cuts.len();
+        let mut result = vec![self.init; df.rows()];
+        result
+            .par_chunks_mut(PREDICT_BLOCK)
+            .enumerate()
+            .for_each(|(block, out)| {
+                let first = block * PREDICT_BLOCK;
+                let mut bins = vec![0; out.len() * n_features];
+                if n_features > 0 {
+                    for (row, row_bins) in bins.chunks_mut(n_features).enumerate() {
+                        self.bin_row(df.row(first + row), row_bins);
+                    }
+                }
+                for (tree, weight) in self.trees.iter().zip(&self.weights) {
+                    for (row, out) in out.iter_mut().enumerate() {
+                        let row_bins = if n_features > 0 {
+                            Some(&bins[row * n_features..(row + 1) * n_features])
+                        } else {
+                            None
+                        };
+                        *out += weight * tree.param[tree.leaf_of(df.row(first + row), row_bins)];
+                    }
+                }
+                for out in out.iter_mut() {
+                    *out = self.transform.apply(*out);
+                }
+            });
+        DataFrame::from_shape_vec((1, df.rows()), result).unwrap()
+    }
+}
+
+impl DecisionTree {
+    /// The tree as a frozen inference-only model
+    pub fn compile(&self) -> FlatEnsemble {
+        FlatEnsemble::new(std::slice::from_ref(self), vec![1.0], 0.0)
+    }
+}
+
+impl RandomForest<DecisionTree> {
+    /// The forest as a frozen inference-only model
+    pub fn compile(&self) -> FlatEnsemble {
+        if self.learners.is_empty() {
+            panic!("Random Forest is not trained");
+        }
+        let weight = 1.0 / self.learners.len() as V;
+        FlatEnsemble::new(&self.learners, vec![weight; self.learners.len()], 0.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::boosting::*;
+    use crate::data_frame::*;
+    use crate::learner::Learner;
+    use crate::loss::BoostingLoss;
+    use crate::random_forest::*;
+    use crate::tree::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn compiled_models_predict_the_same() {
+        // a numerical feature with missing values and a categorical one
+        let x: Vec<V> = (0..600)
+            .flat_map(|i| {
+                let a = if i % 17 == 0 { V::NAN } else { ((i * 7919) % 101) as V };
+                vec![a, (i % 6) as V]
+            })
+            .collect();
+        let y: Vec<V> = (0..600)
+            .map(|i| {
+                let a = x[i * 2];
+                (if a.is_nan() { 30.0 } else { a }) + if i % 6 < 2 { 50.0 } else { 0.0 }
+            })
+            .collect();
+        let x = DataFrame::from_shape_vec((600, 2), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 600), y).unwrap();
+        let tree = || {
+            let mut config = HashSet::new();
+            config.insert(DecisionTreeConfig::MaxDepth(6));
+            config.insert(DecisionTreeConfig::Categorical(vec![1]));
+            DecisionTree::new_with_config(config)
+        };
+
+        let mut forest = RandomForest::from_configs(
+            tree(),
+            vec![
+                RandomForestConfig::NEstimators(5),
+                RandomForestConfig::SubSample(0.5),
+            ],
+        );
+        forest.fit(&x, &y);
+        let mut boost = GradientBoosting::with_config(vec![GBDTConfig::MaxIterations(5)], tree());
+        boost.fit(&x, &y);
+        // predicts exp of its sum
+        let mut poisson = GradientBoosting::with_config(
+            vec![GBDTConfig::MaxIterations(5), GBDTConfig::Loss(BoostingLoss::Poisson)],
+            tree(),
+        );
+        poisson.fit(&x, &y);
+        let mut single = tree();
+        single.fit(&x, &y);
+
+        for (compiled, expected) in [
+            (single.compile(), single.predict(&x)),
+            (forest.compile(), forest.predict(&x)),
+            (boost.compile(), boost.predict(&x)),
+            (poisson.compile(), poisson.predict(&x)),
+        ] {
+            let mut quantized = compiled.clone();
+            quantized.quantize();
+            for model in [compiled, quantized] {
+                let pred = model.predict(&x);
+                for (i, (a, b)) in pred.iter().zip(expected.iter()).enumerate() {
+                    assert!((a - b).abs() < 1e-3, "{} {}", a, b);
+                    if i % 50 == 0 {
+                        assert!((model.predict_row(x.row(i)) - b).abs() < 1e-3);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use crate::criterion::SplitCriterion;
+use crate::data_frame::*;
+use crate::utils::binning::*;
+use rayon::prelude::*;
+
+/// Sufficient statistics of the labels in a node or a histogram bin
+#[derive(Debug, Clone, Default)]
+pub struct NodeStats {
+    /// Number of samples
+    pub count: usize,
+    /// Total weight of samples
+    pub weight: f64,
+    /// Weighted sum of labels
+    pub sum: f64,
+    /// Weighted sum of squared labels
+    pub sum_sq: f64,
+    /// Total weight of each class, empty for regression
+    pub classes: Vec<f64>,
+}
+
+impl NodeStats {
+    pub fn new(n_classes: usize) -> Self {
+        Self {
+            classes: vec![0.0; n_classes],
+            ..Self::default()
+        }
+    }
+
+    pub fn from_samples(
+        samples: &[usize],
+        labels: &DataFrame,
+        weights: &[V],
+        n_classes: usize,
+    ) -> Self {
+        let mut stats = Self::new(n_classes);
+        for index in samples {
+            stats.add(labels[[0, *index]], weights[*index]);
+        }
+        stats
+    }
+
+    #[inline]
+    pub fn add(&mut self, y: V, weight: V) {
+        let (y, weight) = (y as f64, weight as f64);
+        self.count += 1;
+        self.weight += weight;
+        self.sum += weight * y;
+        self.sum_sq += weight * y * y;
+        if !self.classes.is_empty() {
+            self.classes[y as usize] += weight;
+        }
+    }
+
+    #[inline]
+    pub fn merge(&mut self, other: &NodeStats) {
+        self.count += other.count;
+        self.weight += other.weight;
+        self.sum += other.sum;
+        self.sum_sq += other.sum_sq;
+        for (class, other) in self.classes.iter_mut().zip(&other.classes) {
+            *class += other;
+        }
+    }
+
+    /// Statistics of the samples in `self` but not in `other`
+    #[inline]
+    pub fn difference(&self, other: &NodeStats) -> NodeStats {
+        NodeStats {
+            count: self.count - other.count,
+            weight: self.weight - other.weight,
+            sum: self.sum - other.sum,
+            sum_sq: self.sum_sq - other.sum_sq,
+            classes: self
+                .classes
+                .iter()
+                .zip(&other.classes)
+                .map(|(a, b)| a - b)
+                .collect(),
+        }
+    }
+
+    pub fn mean(&self) -> V {
+        (self.sum / self.weight) as V
+    }
+
+    /// The weighted square sum of all labels minus their mean
+    pub fn sse(&self) -> V {
+        if self.weight <= 0.0 {
+            return 0.0;
+        }
+        (self.sum_sq - self.sum * self.sum / self.weight).max(0.0) as V
+    }
+
+    /// The class with the largest weight
+    pub fn majority_class(&self) -> usize {
+        self.classes
+            .iter()
+            .enumerate()
+            .fold((0, f64::NEG_INFINITY), |best, (class, weight)| {
+                if *weight > best.1 {
+                    (class, *weight)
+                } else {
+                    best
+                }
+            })
+            .0
+    }
+
+    /// The fraction of weight of each class
+    pub fn class_distribution(&self) -> Vec<V> {
+        self.classes
+            .iter()
+            .map(|w| (w / self.weight) as V)
+            .collect()
+    }
+}
+
+/// The proxy impurity of sending the present values in `left` to the left child and the
+/// other present values of `total` to the right one, `missing` values go to the side that
+/// scores lower, or to the child with more samples when there are none.
+/// With a `monotone` direction of 1 (-1), the mean of the left child must not be above
+/// (below) the right one's, 0 leaves the split unconstrained.
+/// Returns the impurity and whether missing values go left, `None` if a child would
+/// have fewer than `min_samples_leaf` samples or the split breaks the monotone direction.
+#[inline]
+pub fn score_split(
+    criterion: &dyn SplitCriterion,
+    total: &NodeStats,
+    left: &NodeStats,
+    missing: &NodeStats,
+    min_samples_leaf: usize,
+    monotone: i8,
+) -> Option<(V, bool)> {
+    let min_samples_leaf = min_samples_leaf.max(1);
+    let valid = |left: &NodeStats, right: &NodeStats| {
+        left.count >= min_samples_leaf
+            && right.count >= min_samples_leaf
+            && (monotone == 0 || monotone as V * (right.mean() - left.mean()) >= 0.0)
+    };
+    let right = total.difference(left);
+    if missing.count == 0 {
+        if !valid(left, &right) {
+            return None;
+        }
+        return Some((criterion.proxy_impurity(left, &right), left.count > right.count));
+    }
+
+    let mut best = None;
+    let mut missing_left = left.clone();
+    missing_left.merge(missing);
+    let present_right = right.difference(missing);
+    if valid(&missing_left, &present_right) {
+        best = Some((criterion.proxy_impurity(&missing_left, &present_right), true));
+    }
+    if valid(left, &right) {
+        let impurity = criterion.proxy_impurity(left, &right);
+        if best.is_none_or(|(best_impurity, _)| impurity < best_impurity) {
+            best = Some((impurity, false));
+        }
+    }
+    best
+}
+
+/// The best split found in the histogram of one feature
+#[derive(Debug, Clone)]
+pub struct HistSplit {
+    /// Bins `0..=bin` go to the left child
+    pub bin: usize,
+    /// Whether the missing bin goes to the left child
+    pub default_left: bool,
+    /// Proxy impurity of the split, see `SplitCriterion::proxy_impurity`
+    pub impurity: V,
+}
+
+/// Label statistics of the samples of a node, bucketed by the bins of every feature.
+/// The last bin of a feature holds its missing values.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    pub features: Vec<Vec<NodeStats>>,
+}
+
+impl Histogram {
+    pub fn build(
+        binned: &BinnedFrame,
+        samples: &[usize],
+        labels: &DataFrame,
+        weights: &[V],
+        n_classes: usize,
+    ) -> Self {
+        let node_labels: Vec<(V, V)> = samples
+            .iter()
+            .map(|i| (labels[[0, *i]], weights[*i]))
+            .collect();
+        let features = (0..binned.cols())
+            .into_par_iter()
+            .map(|feature| {
+                let codes = &binned.codes[feature];
+                let mut bins =
+                    vec![NodeStats::new(n_classes); binned.mappers[feature].missing_bin() + 1];
+                for (index, (y, weight)) in samples.iter().zip(&node_labels) {
+                    bins[codes.get(*index)].add(*y, *weight);
+                }
+                bins
+            })
+            .collect();
+        Self { features }
+    }
+
+    /// Histogram subtraction trick: the histogram of a sibling is
+    /// the parent's histogram minus the child's one
+    pub fn subtract(&self, child: &Histogram) -> Histogram {
+        let features = self
+            .features
+            .par_iter()
+            .zip(child.features.par_iter())
+            .map(|(parent, child)| {
+                parent
+                    .iter()
+                    .zip(child)
+                    .map(|(p, c)| p.difference(c))
+                    .collect()
+            })
+            .collect();
+        Histogram { features }
+    }
+
+    /// Statistics of all samples in the histogram
+    pub fn total(&self) -> NodeStats {
+        let n_classes = self.features.first().map_or(0, |bins| bins[0].classes.len());
+        let mut total = NodeStats::new(n_classes);
+        if let Some(bins) = self.features.first() {
+            for bin in bins {
+                total.merge(bin);
+            }
+        }
+        total
+    }
+
+    /// Scan the bins of a feature for the split with the least proxy impurity,
+    /// children must have at least `min_samples_leaf` samples and follow the
+    /// `monotone` direction, see `score_split`
+    pub fn find_split(
+        &self,
+        feature: usize,
+        total: &NodeStats,
+        min_samples_leaf: usize,
+        monotone: i8,
+        criterion: &dyn SplitCriterion,
+    ) -> Option<HistSplit> {
+        let (bins, missing) = self.features[feature].split_at(self.features[feature].len() - 1);
+        let missing = &missing[0];
+        let mut left = NodeStats::new(total.classes.len());
+        let mut best: Option<HistSplit> = None;
+        for (bin, stats) in bins.iter().enumerate() {
+            left.merge(stats);
+            // sending every present value left only splits off the missing values
+            if bin + 1 == bins.len() && missing.count == 0 {
+                break;
+            }
+            let Some((impurity, default_left)) =
+                score_split(criterion, total, &left, missing, min_samples_leaf, monotone)
+            else {
+                continue;
+            };
+            if best.as_ref().is_none_or(|b| impurity < b.impurity) {
+                best = Some(HistSplit {
+                    bin,
+                    default_left,
+                    impurity,
+                });
+            }
+        }
+        best
+    }
+}
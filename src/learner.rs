@@ -1,9 +1,78 @@
 use crate::data_frame::*;
+use ndarray::ArrayView1;
+use rayon::prelude::*;
+
+/// Rows ensembles predict together, every learner goes through a block before the next
+/// one so its nodes stay in cache
+pub const PREDICT_BLOCK: usize = 256;
 
 pub trait Learner {
     /// df: `[samples, features]` y: `[1, samples]`
-    fn fit(&mut self, x: &DataFrame, y: &DataFrame);
+    fn fit(&mut self, x: &DataFrame, y: &DataFrame) {
+        self.fit_weighted(x, y, &DataFrame::ones((1, y.cols())));
+    }
+
+    /// Fits with a non-negative weight for each sample, w: `[1, samples]`
+    fn fit_weighted(&mut self, x: &DataFrame, y: &DataFrame, w: &DataFrame);
+
+    /// Fits on rows coming in consecutive query groups of the sizes `groups`, learners that
+    /// don't rank ignore the groups
+    fn fit_grouped(&mut self, x: &DataFrame, y: &DataFrame, w: &DataFrame, _groups: &[usize]) {
+        self.fit_weighted(x, y, w);
+    }
+
+    /// Whether the learner can only fit rows in query groups, with `fit_grouped`
+    fn needs_groups(&self) -> bool {
+        false
+    }
 
     /// df: `[sample, features]`
     fn predict(&self, df: &DataFrame) -> DataFrame;
+
+    /// The prediction of a single row, ensembles sum it over their learners.
+    /// The default predicts a one-row frame, learners should walk the row directly.
+    fn predict_row(&self, row: ArrayView1<V>) -> V {
+        let df = row.to_owned().into_shape((1, row.len())).unwrap();
+        self.predict(&df)[[0, 0]]
+    }
+
+    /// Sets the value of every leaf to `value` of the rows of `x` falling into it, within
+    /// the leaf's monotone bounds; leaves without rows or whose `value` is `None` keep
+    /// theirs. Returns false for learners without leaves.
+    fn update_leaves(&mut self, _x: &DataFrame, _value: &dyn Fn(&[usize]) -> Option<V>) -> bool {
+        false
+    }
+
+    /// Seeds every random choice of the following fits, learners without any ignore it
+    fn set_seed(&mut self, _seed: u64) {}
+}
+
+/// Sums of `weight(i) * learners[i].predict_row` for every row of `df` as `[outputs, rows]`,
+/// computed in one parallel pass over blocks of rows without allocating per learner.
+/// There is one output per value of `init` they start from, learner `i` adds to
+/// output `i % init.len()`.
+pub fn predict_sum<L, W>(learners: &[L], weight: W, init: &[V], df: &DataFrame) -> DataFrame
+where
+    L: Learner + Sync,
+    W: Fn(usize) -> V + Sync,
+{
+    let outputs = init.len();
+    // the outputs of a row side by side
+    let mut result: Vec<V> = (0..df.rows() * outputs).map(|i| init[i % outputs]).collect();
+    result
+        .par_chunks_mut(PREDICT_BLOCK * outputs)
+        .enumerate()
+        .for_each(|(block, out)| {
+            let first = block * PREDICT_BLOCK;
+            for (i, learner) in learners.iter().enumerate() {
+                let weight = weight(i);
+                let output = i % outputs;
+                for (row, out) in out.chunks_mut(outputs).enumerate() {
+                    out[output] += weight * learner.predict_row(df.row(first + row));
+                }
+            }
+        });
+    DataFrame::from_shape_fn((outputs, df.rows()), |(output, row)| {
+        result[row * outputs + output]
+    })
 }
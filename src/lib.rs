@@ -1,6 +1,13 @@
 pub mod boosting;
+pub mod categorical;
+pub mod criterion;
 pub mod data_frame;
+pub mod flat;
+pub mod histogram;
 pub mod learner;
+pub mod loss;
+pub mod pruning;
 pub mod random_forest;
+pub mod ranking;
 pub mod tree;
 pub mod utils;
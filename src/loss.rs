@@ -0,0 +1,386 @@
+use crate::data_frame::*;
+use serde::{Deserialize, Serialize};
+
+/// The loss `GradientBoosting` minimizes
+#[derive(Debug, Default, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum BoostingLoss {
+    /// Half the squared error, the model predicts the conditional mean
+    #[default]
+    SquaredError,
+    /// Least absolute deviation, the model predicts the conditional median
+    AbsoluteError,
+    /// Squared error for residuals up to `delta`, absolute error beyond
+    Huber(V),
+    /// Pinball loss of the `alpha` quantile, the model predicts the conditional quantile
+    Quantile(V),
+    /// Log-loss of 0/1 labels, the model predicts the log-odds of label 1
+    Logistic,
+    /// Multinomial log-loss of class indexes, the model predicts the logits of every class
+    Softmax,
+    /// Poisson deviance of non-negative counts on a log link
+    Poisson,
+    /// Gamma deviance of positive labels on a log link
+    Gamma,
+    /// Tweedie deviance with variance power `1 < power < 2` of non-negative labels on a log link
+    Tweedie(V),
+    /// LambdaMART ranking of the rows of each query group by non-negative relevance labels
+    LambdaMart,
+}
+
+impl BoostingLoss {
+    /// The implementation of the loss
+    pub fn loss(&self) -> Box<dyn Loss> {
+        match *self {
+            BoostingLoss::SquaredError => Box::new(SquaredError),
+            BoostingLoss::AbsoluteError => Box::new(Quantile { alpha: 0.5 }),
+            BoostingLoss::Huber(delta) => Box::new(Huber { delta }),
+            BoostingLoss::Quantile(alpha) => {
+                if !(alpha > 0.0 && alpha < 1.0) {
+                    panic!("Quantile loss needs 0 < alpha < 1, found {}", alpha);
+                }
+                Box::new(Quantile { alpha })
+            }
+            BoostingLoss::Logistic => Box::new(Logistic),
+            BoostingLoss::Softmax => {
+                panic!("the softmax loss depends on the margins of every class")
+            }
+            BoostingLoss::LambdaMart => {
+                panic!("the LambdaMART loss depends on the scores of every row of a query group")
+            }
+            BoostingLoss::Poisson => Box::new(Poisson),
+            BoostingLoss::Gamma => Box::new(Gamma),
+            BoostingLoss::Tweedie(power) => {
+                if !(power > 1.0 && power < 2.0) {
+                    panic!("Tweedie loss needs 1 < power < 2, found {}", power);
+                }
+                Box::new(Tweedie { power })
+            }
+        }
+    }
+}
+
+/// A differentiable loss of a prediction.
+///
+/// Each boosting step fits a learner to the Newton step `-gradient / hessian` with sample
+/// weights scaled by the hessian, or to the negative gradient for losses without one.
+/// Losses whose gradient says little about the step size re-estimate the value of each
+/// leaf from its residuals with `leaf_value`. Losses on a log link take `pred` as the log
+/// of the predicted mean.
+pub trait Loss: Sync {
+    /// The loss of predicting `pred` for the label `y`
+    fn loss(&self, y: V, pred: V) -> V;
+
+    /// The derivative of the loss in the prediction
+    fn gradient(&self, y: V, pred: V) -> V;
+
+    /// The second derivative of the loss in the prediction, `None` if it is not used
+    fn hessian(&self, _y: V, _pred: V) -> Option<V> {
+        None
+    }
+
+    /// Panics if the loss is not defined for some of the `labels`
+    fn check_labels(&self, _labels: &DataFrame) {}
+
+    /// The constant prediction minimizing the loss of `targets`, given as `(label, weight)`
+    fn init_value(&self, targets: &mut [(V, V)]) -> V;
+
+    /// The value of a leaf holding the `(y - pred, weight)` of its samples, `None` if the
+    /// loss keeps the leaf values fitted to the gradient
+    fn leaf_value(&self, _residuals: &mut [(V, V)]) -> Option<V> {
+        None
+    }
+
+    /// Whether the model predicts `exp(pred)` rather than `pred`
+    fn log_link(&self) -> bool {
+        false
+    }
+}
+
+/// `(y - pred)^2 / 2`
+pub struct SquaredError;
+
+impl Loss for SquaredError {
+    fn loss(&self, y: V, pred: V) -> V {
+        (y - pred).powi(2) / 2.0
+    }
+
+    fn gradient(&self, y: V, pred: V) -> V {
+        pred - y
+    }
+
+    fn hessian(&self, _y: V, _pred: V) -> Option<V> {
+        Some(1.0)
+    }
+
+    fn init_value(&self, targets: &mut [(V, V)]) -> V {
+        let (sum, weight) = targets.iter().fold((0.0, 0.0), |(sum, weight), (y, w)| {
+            (sum + (*y as f64) * (*w as f64), weight + *w as f64)
+        });
+        if weight > 0.0 {
+            (sum / weight) as V
+        } else {
+            0.0
+        }
+    }
+}
+
+/// `delta * (|y - pred| - delta / 2)` beyond `delta`, `(y - pred)^2 / 2` below
+pub struct Huber {
+    pub delta: V,
+}
+
+impl Loss for Huber {
+    fn loss(&self, y: V, pred: V) -> V {
+        let diff = (y - pred).abs();
+        if diff <= self.delta {
+            diff * diff / 2.0
+        } else {
+            self.delta * (diff - self.delta / 2.0)
+        }
+    }
+
+    fn gradient(&self, y: V, pred: V) -> V {
+        (pred - y).clamp(-self.delta, self.delta)
+    }
+
+    fn init_value(&self, targets: &mut [(V, V)]) -> V {
+        weighted_quantile(targets, 0.5)
+    }
+
+    /// The median of the residuals plus the mean of their clipped deviations from it,
+    /// one Newton-like step from the median
+    fn leaf_value(&self, residuals: &mut [(V, V)]) -> Option<V> {
+        let median = weighted_quantile(residuals, 0.5);
+        let (sum, weight) = residuals.iter().fold((0.0, 0.0), |(sum, weight), (r, w)| {
+            let clipped = (r - median).clamp(-self.delta, self.delta);
+            (sum + (clipped * w) as f64, weight + *w as f64)
+        });
+        Some(if weight > 0.0 {
+            median + (sum / weight) as V
+        } else {
+            median
+        })
+    }
+}
+
+/// Pinball loss: `alpha * (y - pred)` above the prediction, `(1 - alpha) * (pred - y)` below
+pub struct Quantile {
+    pub alpha: V,
+}
+
+impl Loss for Quantile {
+    fn loss(&self, y: V, pred: V) -> V {
+        let diff = y - pred;
+        if diff >= 0.0 {
+            self.alpha * diff
+        } else {
+            (self.alpha - 1.0) * diff
+        }
+    }
+
+    fn gradient(&self, y: V, pred: V) -> V {
+        if y > pred {
+            -self.alpha
+        } else {
+            1.0 - self.alpha
+        }
+    }
+
+    fn init_value(&self, targets: &mut [(V, V)]) -> V {
+        weighted_quantile(targets, self.alpha)
+    }
+
+    fn leaf_value(&self, residuals: &mut [(V, V)]) -> Option<V> {
+        Some(weighted_quantile(residuals, self.alpha))
+    }
+}
+
+/// Binary log-loss `log(1 + exp(pred)) - y * pred` of the log-odds `pred`
+pub struct Logistic;
+
+impl Loss for Logistic {
+    fn loss(&self, y: V, pred: V) -> V {
+        // log(1 + exp(pred)) without overflow
+        pred.max(0.0) + (-pred.abs()).exp().ln_1p() - y * pred
+    }
+
+    fn gradient(&self, y: V, pred: V) -> V {
+        sigmoid(pred) - y
+    }
+
+    fn hessian(&self, _y: V, pred: V) -> Option<V> {
+        let p = sigmoid(pred);
+        Some(p * (1.0 - p))
+    }
+
+    fn check_labels(&self, labels: &DataFrame) {
+        if labels.iter().any(|y| *y != 0.0 && *y != 1.0) {
+            panic!("Logistic loss needs 0/1 labels");
+        }
+    }
+
+    /// The log-odds of the weighted fraction of positive labels
+    fn init_value(&self, targets: &mut [(V, V)]) -> V {
+        let p = SquaredError.init_value(targets).clamp(1e-6, 1.0 - 1e-6);
+        (p / (1.0 - p)).ln()
+    }
+}
+
+/// `exp(pred) - y * pred`, half the Poisson deviance up to a constant
+pub struct Poisson;
+
+impl Loss for Poisson {
+    fn loss(&self, y: V, pred: V) -> V {
+        pred.exp() - y * pred
+    }
+
+    fn gradient(&self, y: V, pred: V) -> V {
+        pred.exp() - y
+    }
+
+    fn hessian(&self, _y: V, pred: V) -> Option<V> {
+        Some(pred.exp())
+    }
+
+    fn check_labels(&self, labels: &DataFrame) {
+        if labels.iter().any(|y| y.is_nan() || *y < 0.0) {
+            panic!("Poisson loss needs non-negative labels");
+        }
+    }
+
+    fn init_value(&self, targets: &mut [(V, V)]) -> V {
+        log_mean(targets)
+    }
+
+    fn log_link(&self) -> bool {
+        true
+    }
+}
+
+/// `y * exp(-pred) + pred`, half the Gamma deviance up to a constant
+pub struct Gamma;
+
+impl Loss for Gamma {
+    fn loss(&self, y: V, pred: V) -> V {
+        y * (-pred).exp() + pred
+    }
+
+    fn gradient(&self, y: V, pred: V) -> V {
+        1.0 - y * (-pred).exp()
+    }
+
+    fn hessian(&self, y: V, pred: V) -> Option<V> {
+        Some(y * (-pred).exp())
+    }
+
+    fn check_labels(&self, labels: &DataFrame) {
+        if labels.iter().any(|y| y.is_nan() || *y <= 0.0) {
+            panic!("Gamma loss needs positive labels");
+        }
+    }
+
+    fn init_value(&self, targets: &mut [(V, V)]) -> V {
+        log_mean(targets)
+    }
+
+    fn log_link(&self) -> bool {
+        true
+    }
+}
+
+/// `exp((2 - p) * pred) / (2 - p) - y * exp((1 - p) * pred) / (1 - p)` for the variance
+/// power `p`, half the Tweedie deviance up to a constant
+pub struct Tweedie {
+    pub power: V,
+}
+
+impl Loss for Tweedie {
+    fn loss(&self, y: V, pred: V) -> V {
+        let (a, b) = (1.0 - self.power, 2.0 - self.power);
+        (b * pred).exp() / b - y * (a * pred).exp() / a
+    }
+
+    fn gradient(&self, y: V, pred: V) -> V {
+        let (a, b) = (1.0 - self.power, 2.0 - self.power);
+        (b * pred).exp() - y * (a * pred).exp()
+    }
+
+    fn hessian(&self, y: V, pred: V) -> Option<V> {
+        let (a, b) = (1.0 - self.power, 2.0 - self.power);
+        Some(b * (b * pred).exp() - a * y * (a * pred).exp())
+    }
+
+    fn check_labels(&self, labels: &DataFrame) {
+        if labels.iter().any(|y| y.is_nan() || *y < 0.0) {
+            panic!("Tweedie loss needs non-negative labels");
+        }
+    }
+
+    fn init_value(&self, targets: &mut [(V, V)]) -> V {
+        log_mean(targets)
+    }
+
+    fn log_link(&self) -> bool {
+        true
+    }
+}
+
+/// The log of the weighted mean of `targets`, given as `(label, weight)`
+fn log_mean(targets: &mut [(V, V)]) -> V {
+    SquaredError.init_value(targets).max(1e-6).ln()
+}
+
+pub fn sigmoid(x: V) -> V {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// Turns the logits of every class into probabilities in place
+pub fn softmax(logits: &mut [V]) {
+    let max = logits.iter().fold(V::NEG_INFINITY, |max, l| max.max(*l));
+    let mut sum = 0.0;
+    for l in logits.iter_mut() {
+        *l = (*l - max).exp();
+        sum += *l;
+    }
+    for l in logits.iter_mut() {
+        *l /= sum;
+    }
+}
+
+/// The multinomial log-loss `-log(softmax(logits)[class])` of `n_classes` logits
+pub fn softmax_loss(logit: impl Fn(usize) -> V, n_classes: usize, class: usize) -> V {
+    let max = (0..n_classes).fold(V::NEG_INFINITY, |max, c| max.max(logit(c)));
+    let sum: V = (0..n_classes).map(|c| (logit(c) - max).exp()).sum();
+    max + sum.ln() - logit(class)
+}
+
+/// The smallest value whose cumulative weight reaches `alpha` of the total weight,
+/// `values` are `(value, weight)` and get sorted
+pub fn weighted_quantile(values: &mut [(V, V)], alpha: V) -> V {
+    values.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let total: f64 = values.iter().map(|(_, w)| *w as f64).sum();
+    let mut cumulative = 0.0;
+    for (value, weight) in values.iter() {
+        cumulative += *weight as f64;
+        if cumulative >= alpha as f64 * total {
+            return *value;
+        }
+    }
+    values.last().map_or(0.0, |(value, _)| *value)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::loss::*;
+
+    #[test]
+    fn quantile_init_values() {
+        let mut targets: Vec<(V, V)> = [5.0, 1.0, 3.0, 100.0, 2.0].iter().map(|y| (*y, 1.0)).collect();
+        assert_eq!(3.0, BoostingLoss::AbsoluteError.loss().init_value(&mut targets));
+        assert_eq!(5.0, BoostingLoss::Quantile(0.7).loss().init_value(&mut targets));
+        assert_eq!(22.2, BoostingLoss::SquaredError.loss().init_value(&mut targets));
+        // a heavy sample moves the median
+        targets.iter_mut().find(|t| t.0 == 2.0).unwrap().1 = 10.0;
+        assert_eq!(2.0, BoostingLoss::Huber(1.0).loss().init_value(&mut targets));
+    }
+}
@@ -0,0 +1,275 @@
+use crate::data_frame::*;
+use crate::tree::*;
+use serde::{Deserialize, Serialize};
+
+/// The sequence of subtrees minimal cost-complexity pruning goes through
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PruningPath {
+    /// Increasing effective alphas, the first one is 0 for the unpruned tree
+    pub ccp_alphas: Vec<V>,
+    /// The total leaf impurity of the subtree pruned with each alpha
+    pub impurities: Vec<V>,
+}
+
+impl DecisionTree {
+    /// The effective alphas of minimal cost-complexity pruning and the impurity of
+    /// the subtree each of them leaves, until only the root remains
+    pub fn cost_complexity_pruning_path(&self) -> PruningPath {
+        self.weakest_link_pruning(V::INFINITY).0
+    }
+
+    /// Minimal cost-complexity pruning: prunes the subtrees whose effective alpha is at most
+    /// `ccp_alpha`, the remaining nodes are compacted
+    pub fn prune_cost_complexity(&mut self, ccp_alpha: V) {
+        let (_, pruned) = self.weakest_link_pruning(ccp_alpha);
+        for index in pruned {
+            self.nodes[index].info = NodeInfo::Leaf;
+        }
+        self.compact();
+    }
+
+    /// Reduced-error pruning on a held-out set: from the bottom up, a stem whose children are
+    /// leaves is turned into a leaf unless that increases the error of the held-out samples
+    /// reaching it. The error is the square error, or the number of misclassified samples
+    /// of a classification tree. The remaining nodes are compacted.
+    pub fn prune_reduced_error(&mut self, x: &DataFrame, y: &DataFrame) {
+        if self.nodes.is_empty() {
+            panic!("Model is not trained!");
+        }
+        let classification = self.criterion.is_classification();
+        let error = |value: V, y: V| {
+            if classification {
+                (value != y) as u8 as f64
+            } else {
+                (value - y).powi(2) as f64
+            }
+        };
+
+        // the held-out samples going through each node
+        let mut samples: Vec<Vec<usize>> = vec![vec![]; self.nodes.len()];
+        for row in 0..x.rows() {
+            let mut current = 0;
+            samples[current].push(row);
+            while let NodeInfo::Stem { feature, left, right, .. }
+            | NodeInfo::CategoryStem { feature, left, right, .. } = self.nodes[current].info
+            {
+                current = if self.nodes[current].info.goes_left(x[[row, feature]]) {
+                    left
+                } else {
+                    right
+                };
+                samples[current].push(row);
+            }
+        }
+
+        // children come after their parent, so they are visited first
+        let mut is_leaf: Vec<bool> = self.nodes.iter().map(|n| matches!(n.info, NodeInfo::Leaf)).collect();
+        for index in (0..self.nodes.len()).rev() {
+            let (left, right) = match self.nodes[index].info {
+                NodeInfo::Leaf => continue,
+                NodeInfo::Stem { left, right, .. } | NodeInfo::CategoryStem { left, right, .. } => {
+                    (left, right)
+                }
+            };
+            if !is_leaf[left] || !is_leaf[right] {
+                continue;
+            }
+            let node_error = |node: usize, rows: &[usize]| -> f64 {
+                rows.iter().map(|row| error(self.nodes[node].value, y[[0, *row]])).sum()
+            };
+            let leaf_error = node_error(index, &samples[index]);
+            let subtree_error = node_error(left, &samples[left]) + node_error(right, &samples[right]);
+            if leaf_error <= subtree_error {
+                is_leaf[index] = true;
+            }
+        }
+        for (node, is_leaf) in self.nodes.iter_mut().zip(is_leaf) {
+            if is_leaf {
+                node.info = NodeInfo::Leaf;
+            }
+        }
+        self.compact();
+    }
+
+    /// Repeatedly turns the stems with the smallest effective alpha into leaves while it is
+    /// at most `max_alpha`. The effective alpha of a stem is the increase of the total leaf
+    /// impurity per removed leaf when pruning its subtree, impurities are divided by the
+    /// root's weight. Returns the pruning path and the pruned stems.
+    fn weakest_link_pruning(&self, max_alpha: V) -> (PruningPath, Vec<usize>) {
+        let n = self.nodes.len();
+        let total_weight = self.nodes.first().map_or(1.0, |root| root.weight as f64).max(f64::MIN_POSITIVE);
+        let impurity: Vec<f64> = self.nodes.iter().map(|n| n.variance as f64 / total_weight).collect();
+        let children = |index: usize| match self.nodes[index].info {
+            NodeInfo::Leaf => None,
+            NodeInfo::Stem { left, right, .. } | NodeInfo::CategoryStem { left, right, .. } => {
+                Some((left, right))
+            }
+        };
+
+        let mut pruned = vec![false; n];
+        let mut pruned_stems = vec![];
+        let mut path = PruningPath {
+            ccp_alphas: vec![],
+            impurities: vec![],
+        };
+        let mut alpha = 0.0;
+        loop {
+            // leaf impurity and number of leaves of every subtree, children come after their parent
+            let mut subtree_impurity = impurity.clone();
+            let mut leaves = vec![1usize; n];
+            for index in (0..n).rev() {
+                if let (false, Some((left, right))) = (pruned[index], children(index)) {
+                    subtree_impurity[index] = subtree_impurity[left] + subtree_impurity[right];
+                    leaves[index] = leaves[left] + leaves[right];
+                }
+            }
+            path.ccp_alphas.push(alpha as V);
+            path.impurities.push(subtree_impurity.first().map_or(0.0, |i| *i as V));
+
+            // effective alphas of the stems still in the tree
+            let mut in_tree = vec![false; n];
+            let mut stems: Vec<(usize, f64)> = vec![];
+            if n > 0 {
+                in_tree[0] = true;
+            }
+            for index in 0..n {
+                if !in_tree[index] || pruned[index] {
+                    continue;
+                }
+                if let Some((left, right)) = children(index) {
+                    in_tree[left] = true;
+                    in_tree[right] = true;
+                    let effective = (impurity[index] - subtree_impurity[index]) / (leaves[index] - 1) as f64;
+                    stems.push((index, effective.max(0.0)));
+                }
+            }
+            let Some(weakest) = stems.iter().map(|(_, a)| *a).min_by(|a, b| a.total_cmp(b)) else {
+                break;
+            };
+            if weakest > max_alpha as f64 {
+                break;
+            }
+            alpha = weakest;
+            for (index, effective) in stems {
+                if effective <= weakest {
+                    pruned[index] = true;
+                    pruned_stems.push(index);
+                }
+            }
+        }
+        (path, pruned_stems)
+    }
+
+    /// Removes the nodes no longer reachable from the root and renumbers the others,
+    /// keeping children after their parent
+    fn compact(&mut self) {
+        let n = self.nodes.len();
+        let mut reachable = vec![false; n];
+        if n > 0 {
+            reachable[0] = true;
+        }
+        let mut new_index = vec![usize::MAX; n];
+        let mut kept = 0;
+        for index in 0..n {
+            if !reachable[index] {
+                continue;
+            }
+            new_index[index] = kept;
+            kept += 1;
+            if let NodeInfo::Stem { left, right, .. } | NodeInfo::CategoryStem { left, right, .. } =
+                self.nodes[index].info
+            {
+                reachable[left] = true;
+                reachable[right] = true;
+            }
+        }
+
+        let nodes = std::mem::take(&mut self.nodes);
+        self.nodes = nodes
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| reachable[*index])
+            .map(|(index, mut node)| {
+                node.index = new_index[index];
+                if let NodeInfo::Stem { left, right, .. } | NodeInfo::CategoryStem { left, right, .. } =
+                    &mut node.info
+                {
+                    *left = new_index[*left];
+                    *right = new_index[*right];
+                }
+                node
+            })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data_frame::*;
+    use crate::learner::Learner;
+    use crate::tree::*;
+    use std::collections::HashSet;
+
+    /// A noisy step function, a full tree overfits the noise
+    fn noisy_step() -> (DataFrame, DataFrame) {
+        let x: Vec<V> = (0..100).map(|i| i as V).collect();
+        let y: Vec<V> = (0..100)
+            .map(|i| if i < 50 { 0.0 } else { 10.0 } + ((i * 7919) % 13) as V / 13.0)
+            .collect();
+        (
+            DataFrame::from_shape_vec((100, 1), x).unwrap(),
+            DataFrame::from_shape_vec((1, 100), y).unwrap(),
+        )
+    }
+
+    #[test]
+    fn pruning_path_ends_at_root() {
+        let (x, y) = noisy_step();
+        let mut tree = DecisionTree::new();
+        tree.fit(&x, &y);
+        let path = tree.cost_complexity_pruning_path();
+        assert_eq!(0.0, path.ccp_alphas[0]);
+        for pair in path.ccp_alphas.windows(2) {
+            assert!(pair[0] <= pair[1]);
+        }
+        for pair in path.impurities.windows(2) {
+            assert!(pair[0] <= pair[1] + 1e-6);
+        }
+        // the last subtree is the root alone
+        assert!((path.impurities.last().unwrap() - tree.nodes[0].variance / 100.0).abs() < 1e-4);
+
+        // an alpha between the last two keeps the split of the step
+        let n = path.ccp_alphas.len();
+        tree.prune_cost_complexity((path.ccp_alphas[n - 2] + path.ccp_alphas[n - 1]) / 2.0);
+        assert_eq!(3, tree.nodes.len());
+        assert!(matches!(tree.nodes[0].info, NodeInfo::Stem { left: 1, right: 2, .. }));
+    }
+
+    #[test]
+    fn ccp_alpha_prunes_while_fitting() {
+        let (x, y) = noisy_step();
+        let mut config = HashSet::new();
+        config.insert(DecisionTreeConfig::CcpAlpha(1.0));
+        let mut tree = DecisionTree::new_with_config(config);
+        tree.fit(&x, &y);
+        assert_eq!(3, tree.nodes.len());
+    }
+
+    #[test]
+    fn reduced_error_pruning_removes_noise_splits() {
+        let (x, y) = noisy_step();
+        let mut tree = DecisionTree::new();
+        tree.fit(&x, &y);
+        // held-out labels without noise
+        let clean = y.mapv(|v| v.floor());
+        tree.prune_reduced_error(&x, &clean);
+        assert!(tree.nodes.len() < 100);
+        for (index, node) in tree.nodes.iter().enumerate() {
+            assert_eq!(index, node.index);
+        }
+        let pred = tree.predict(&x);
+        for i in 0..100 {
+            assert_eq!(clean[[0, i]] >= 10.0, pred[[0, i]] >= 5.0);
+        }
+    }
+}
@@ -1,8 +1,11 @@
 use crate::data_frame::*;
 use crate::learner::*;
 use crate::utils::numeric;
+use crate::utils::random::*;
 use log::*;
-use rand::seq::SliceRandom;
+use ndarray::ArrayView1;
+use rand::prelude::*;
+use rayon::prelude::*;
 use serde::{Serialize, Deserialize};
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -15,12 +18,54 @@ pub struct RandomForest<L> {
     pub n_estimators: usize,
     /// The fraction of samples to train fir individual tree
     pub sub_sample: f64,
+    /// Seed of the subsamples and of the learners, a random one is drawn at each fit if `None`
+    #[serde(default)]
+    pub random_state: Option<u64>,
+    /// Whether subsamples are drawn with replacement
+    #[serde(default)]
+    pub bootstrap: bool,
+    /// Whether fitting predicts each training row by the learners it was out-of-bag for
+    #[serde(default)]
+    pub oob_scoring: bool,
+    /// The mean prediction of each training row by the learners it was out-of-bag for,
+    /// NaN for rows that were in every subsample
+    #[serde(skip)]
+    pub oob_prediction: Option<DataFrame>,
+    /// Weighted R2 score of `oob_prediction` over the rows out-of-bag at least once
+    #[serde(skip)]
+    pub oob_score: Option<V>,
+    /// The sum and number of out-of-bag predictions of each training row by the first
+    /// `oob_learners` learners, carried over warm starts
+    #[serde(skip)]
+    oob_sum: Vec<V>,
+    #[serde(skip)]
+    oob_count: Vec<usize>,
+    #[serde(skip)]
+    oob_learners: usize,
+    /// The out-of-bag rows of each learner as a bitset, `None` for learners fitted without
+    /// OOB scoring, see `oob_indices`
+    #[serde(skip)]
+    oob_masks: Vec<Option<Vec<u64>>>,
+    /// The maximum number of trees trained at once, 0 for the number of threads
+    #[serde(default)]
+    pub max_trees_in_flight: usize,
+    /// Whether to log the score of every tree on the whole training set
+    #[serde(default)]
+    pub score_trees: bool,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub enum RandomForestConfig {
     NEstimators(usize),
     SubSample(f64),
+    RandomState(u64),
+    /// Draw subsamples with replacement, `SubSample` is then their size relative to the data
+    Bootstrap(bool),
+    /// Score the forest on the rows left out of each learner's subsample
+    OobScore(bool),
+    /// Bounds the number of trees trained at once, and so the memory of their subsamples
+    MaxTreesInFlight(usize),
+    ScoreTrees(bool),
 }
 
 impl<L: Learner + Clone + Send + Sync> RandomForest<L> {
@@ -30,6 +75,17 @@ impl<L: Learner + Clone + Send + Sync> RandomForest<L> {
             learners: vec![],
             n_estimators: 100,
             sub_sample: 1.0,
+            random_state: None,
+            bootstrap: false,
+            oob_scoring: false,
+            oob_prediction: None,
+            oob_score: None,
+            oob_sum: vec![],
+            oob_count: vec![],
+            oob_learners: 0,
+            oob_masks: vec![],
+            max_trees_in_flight: 0,
+            score_trees: false,
         }
     }
     pub fn from_configs(base_learner: L, configs: Vec<RandomForestConfig>) -> Self {
@@ -38,61 +94,357 @@ impl<L: Learner + Clone + Send + Sync> RandomForest<L> {
             match config {
                 RandomForestConfig::NEstimators(e) => forest.n_estimators = e,
                 RandomForestConfig::SubSample(s) => forest.sub_sample = s,
+                RandomForestConfig::RandomState(seed) => forest.random_state = Some(seed),
+                RandomForestConfig::Bootstrap(b) => forest.bootstrap = b,
+                RandomForestConfig::OobScore(o) => forest.oob_scoring = o,
+                RandomForestConfig::MaxTreesInFlight(n) => forest.max_trees_in_flight = n,
+                RandomForestConfig::ScoreTrees(s) => forest.score_trees = s,
             }
         }
         forest
     }
 
-    /// Returns the sub sample x, residual and feature orders
-    fn choose_subsample(&self, x: &DataFrame, y: &DataFrame) -> (DataFrame, DataFrame) {
-        let mut rng = rand::thread_rng();
-        let mut orders: Vec<usize> = (0..x.rows()).collect();
-        orders.shuffle(&mut rng);
-        let sub_sample_size = (self.sub_sample * orders.len() as f64) as usize;
-        orders.resize(sub_sample_size, 0);
+    /// The training rows left out of the subsample of the learner `tree`, `None` if it was
+    /// not fitted with OOB scoring
+    pub fn oob_indices(&self, tree: usize) -> Option<Vec<usize>> {
+        let mask = self.oob_masks.get(tree)?.as_ref()?;
+        Some(mask_rows(mask).collect())
+    }
+
+    /// Returns the sub sample x, y and sample weights drawn from the stream `seed`,
+    /// and the bitset of the out-of-bag rows if OOB scoring is on
+    fn choose_subsample(
+        &self,
+        x: &DataFrame,
+        y: &DataFrame,
+        w: &DataFrame,
+        seed: u64,
+    ) -> (DataFrame, DataFrame, DataFrame, Option<Vec<u64>>) {
+        let mut rng = seeded_rng(seed);
+        let sub_sample_size = (self.sub_sample * x.rows() as f64) as usize;
+        let orders: Vec<usize> = if self.bootstrap {
+            (0..sub_sample_size).map(|_| rng.gen_range(0, x.rows())).collect()
+        } else {
+            let mut orders: Vec<usize> = (0..x.rows()).collect();
+            orders.shuffle(&mut rng);
+            orders.truncate(sub_sample_size);
+            orders
+        };
+        let oob = self.oob_scoring.then(|| {
+            let mut in_bag = vec![false; x.rows()];
+            for index in &orders {
+                in_bag[*index] = true;
+            }
+            let mut mask = vec![0u64; x.rows().div_ceil(64)];
+            for index in (0..x.rows()).filter(|i| !in_bag[*i]) {
+                mask[index / 64] |= 1 << (index % 64);
+            }
+            mask
+        });
 
         let mut buffer = Vec::with_capacity(sub_sample_size);
         let mut y_buffer = Vec::with_capacity(sub_sample_size);
+        let mut w_buffer = Vec::with_capacity(sub_sample_size);
         for index in orders {
             buffer.extend(x.row(index).iter());
             y_buffer.push(y[[0, index]]);
+            w_buffer.push(w[[0, index]]);
         }
         (
             DataFrame::from_shape_vec((sub_sample_size, x.cols()), buffer).unwrap(),
             DataFrame::from_shape_vec((1, sub_sample_size), y_buffer).unwrap(),
+            DataFrame::from_shape_vec((1, sub_sample_size), w_buffer).unwrap(),
+            oob,
         )
     }
+
+    /// Sets the out-of-bag prediction and its weighted score from the sum of the
+    /// predictions of each row by the learners it was out-of-bag for
+    fn score_oob(&mut self, y: &DataFrame, w: &DataFrame) {
+        let pred: Vec<V> = self
+            .oob_sum
+            .iter()
+            .zip(&self.oob_count)
+            .map(|(sum, count)| if *count > 0 { sum / *count as V } else { V::NAN })
+            .collect();
+
+        let scored: Vec<usize> = (0..y.cols()).filter(|i| self.oob_count[*i] > 0).collect();
+        self.oob_score = if scored.is_empty() {
+            None
+        } else {
+            let frame = |values: Vec<V>| DataFrame::from_shape_vec((1, scored.len()), values).unwrap();
+            Some(numeric::weighted_r2_score(
+                &frame(scored.iter().map(|i| y[[0, *i]]).collect()),
+                &frame(scored.iter().map(|i| pred[*i]).collect()),
+                &frame(scored.iter().map(|i| w[[0, *i]]).collect()),
+            ))
+        };
+        self.oob_prediction = Some(DataFrame::from_shape_vec((1, y.cols()), pred).unwrap());
+    }
+}
+
+/// The rows set in a bitset of rows
+fn mask_rows(mask: &[u64]) -> impl Iterator<Item = usize> + '_ {
+    (0..mask.len() * 64).filter(move |i| mask[i / 64] & (1 << (i % 64)) != 0)
 }
 
 impl<L: Learner + Clone + Send + Sync> Learner for RandomForest<L> {
-    fn fit(&mut self, x: &DataFrame, y: &DataFrame) {
-        // train all trees in parallel
-        let new_learners: Vec<L> = (0..self.n_estimators)
-            .into_iter()
-            .map(|_n| {
-                let (sub_x, sub_y) = self.choose_subsample(&x, &y);
-                let mut tree = self.tree.clone();
-                tree.fit(&sub_x, &sub_y);
-                let pred = tree.predict(&x);
-                info!("score at step {}: {}", self.learners.len() + _n, numeric::r2_score(&y, &pred));
-
-                tree
-            })
-            .collect();
-        self.learners.extend(new_learners);
+    fn fit_weighted(&mut self, x: &DataFrame, y: &DataFrame, w: &DataFrame) {
+        // every tree gets its own streams, derived from the seed and its index
+        let seed = seed_or_random(self.random_state);
+        let first = self.learners.len();
+        let in_flight = match self.max_trees_in_flight {
+            0 => rayon::current_num_threads(),
+            n => n,
+        };
+        // out-of-bag sums carry over a warm start if every earlier learner was counted
+        // on rows of the same data
+        if self.oob_learners != first || self.oob_count.len() != y.cols() {
+            self.oob_sum = vec![0.0; y.cols()];
+            self.oob_count = vec![0; y.cols()];
+            self.oob_learners = 0;
+        }
+        self.learners.reserve(self.n_estimators);
+        // learners loaded or set by hand have no out-of-bag record
+        self.oob_masks.resize(first, None);
+
+        // train trees in parallel, at most `in_flight` at once
+        let mut start = 0;
+        while start < self.n_estimators {
+            let end = (start + in_flight).min(self.n_estimators);
+            let trained: Vec<_> = (start..end)
+                .into_par_iter()
+                .map(|n| {
+                    let tree_seed = derive_seed(seed, (first + n) as u64);
+                    let (sub_x, sub_y, sub_w, oob) =
+                        self.choose_subsample(x, y, w, derive_seed(tree_seed, 0));
+                    let mut tree = self.tree.clone();
+                    tree.set_seed(derive_seed(tree_seed, 1));
+                    tree.fit_weighted(&sub_x, &sub_y, &sub_w);
+                    if self.score_trees {
+                        let pred = tree.predict(x);
+                        info!("score at step {}: {}", first + n, numeric::r2_score(y, &pred));
+                    }
+                    let oob_pred: Vec<(usize, V)> = oob
+                        .iter()
+                        .flat_map(|mask| mask_rows(mask))
+                        .map(|i| (i, tree.predict_row(x.row(i))))
+                        .collect();
+                    (tree, oob, oob_pred)
+                })
+                .collect();
+
+            for (tree, oob, oob_pred) in trained {
+                for (index, pred) in oob_pred {
+                    self.oob_sum[index] += pred;
+                    self.oob_count[index] += 1;
+                }
+                self.learners.push(tree);
+                self.oob_masks.push(oob);
+            }
+            start = end;
+        }
+
+        if !self.oob_scoring {
+            self.oob_prediction = None;
+            self.oob_score = None;
+            return;
+        }
+        self.oob_learners += self.n_estimators;
+        if self.oob_learners == self.learners.len() {
+            self.score_oob(y, w);
+        } else {
+            warn!("Learners of an earlier fit were not scored out-of-bag, OOB score is unavailable");
+            self.oob_prediction = None;
+            self.oob_score = None;
+        }
     }
 
     fn predict(&self, df: &DataFrame) -> DataFrame {
         if self.learners.is_empty() {
             panic!("Random Forest is not trained");
         }
-        let mut result_sum =
-            DataFrame::from_shape_vec((1, df.rows()), vec![0.0; df.rows()]).unwrap();
+        predict_sum(&self.learners, |_| 1.0, &[0.0], df) / self.learners.len() as V
+    }
+
+    fn predict_row(&self, row: ArrayView1<V>) -> V {
+        let sum: V = self.learners.iter().map(|l| l.predict_row(row)).sum();
+        sum / self.learners.len() as V
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.random_state = Some(seed);
+    }
+}
 
-        for l in &self.learners {
-            result_sum = result_sum + &l.predict(&df);
+#[cfg(test)]
+mod test {
+    use crate::data_frame::*;
+    use crate::learner::Learner;
+    use crate::random_forest::*;
+    use crate::tree::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn same_seed_gives_same_forest() {
+        let x: Vec<V> = (0..600).map(|i| ((i * 7919) % 101) as V).collect();
+        let y: Vec<V> = (0..200).map(|i| x[i * 3] - x[i * 3 + 1] + (i % 5) as V).collect();
+        let x = DataFrame::from_shape_vec((200, 3), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 200), y).unwrap();
+        let fit = |seed: u64, threads: usize| {
+            let mut config = HashSet::new();
+            config.insert(DecisionTreeConfig::MaxFeatures(1));
+            let tree = DecisionTree::new_with_config(config);
+            let mut forest = RandomForest::from_configs(
+                tree,
+                vec![
+                    RandomForestConfig::NEstimators(5),
+                    RandomForestConfig::SubSample(0.5),
+                    RandomForestConfig::RandomState(seed),
+                    RandomForestConfig::MaxTreesInFlight(threads),
+                ],
+            );
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| forest.fit(&x, &y));
+            forest.predict(&x)
+        };
+        // independent of the number of threads and of trees trained at once
+        assert_eq!(fit(7, 1), fit(7, 4));
+        assert_ne!(fit(7, 4), fit(8, 4));
+    }
+
+    #[test]
+    fn bootstrap_scores_out_of_bag_rows() {
+        let x: Vec<V> = (0..300).map(|i| (i % 100) as V).collect();
+        let y: Vec<V> = (0..300).map(|i| ((i % 100) / 10) as V).collect();
+        let x = DataFrame::from_shape_vec((300, 1), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 300), y).unwrap();
+        let forest = |oob: bool| {
+            RandomForest::from_configs(
+                DecisionTree::new(),
+                vec![
+                    RandomForestConfig::NEstimators(3),
+                    RandomForestConfig::Bootstrap(true),
+                    RandomForestConfig::RandomState(1),
+                    RandomForestConfig::OobScore(oob),
+                ],
+            )
+        };
+        let mut unscored = forest(false);
+        unscored.fit(&x, &y);
+        assert!(unscored.oob_prediction.is_none() && unscored.oob_score.is_none());
+
+        let mut forest = forest(true);
+        forest.fit(&x, &y);
+        // about 1/e of the rows are out of each bootstrap sample, (1 - 1/e)^3 in all 3
+        let scored_rows = |forest: &RandomForest<DecisionTree>| {
+            forest.oob_prediction.as_ref().unwrap().iter().filter(|p| !p.is_nan()).count()
+        };
+        let scored = scored_rows(&forest);
+        assert!(scored > 180 && scored < 270, "{}", scored);
+        // every tree records the rows left out of its bootstrap sample
+        for tree in 0..3 {
+            let oob = forest.oob_indices(tree).unwrap();
+            assert!(oob.len() > 80 && oob.len() < 140, "{}", oob.len());
+            assert!(oob.iter().all(|i| *i < 300));
+        }
+        // a warm start keeps the out-of-bag predictions of the earlier learners
+        forest.n_estimators = 17;
+        forest.fit(&x, &y);
+        assert_eq!(20, forest.learners.len());
+        assert_eq!(300, scored_rows(&forest));
+        assert!(forest.oob_score.unwrap() > 0.9);
+        assert_eq!(&[1, 300], forest.oob_prediction.as_ref().unwrap().shape());
+
+        // without the out-of-bag predictions of earlier learners there is no score
+        unscored.oob_scoring = true;
+        unscored.fit(&x, &y);
+        assert!(unscored.oob_score.is_none());
+        assert!(unscored.oob_indices(0).is_none() && unscored.oob_indices(3).is_some());
+    }
+
+    #[test]
+    fn fused_prediction_matches_learners() {
+        // more rows than a prediction block
+        let x: Vec<V> = (0..1000).map(|i| ((i * 7919) % 101) as V).collect();
+        let y: Vec<V> = (0..500).map(|i| x[i * 2] - x[i * 2 + 1]).collect();
+        let x = DataFrame::from_shape_vec((500, 2), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 500), y).unwrap();
+        let mut forest = RandomForest::from_configs(
+            DecisionTree::new(),
+            vec![
+                RandomForestConfig::NEstimators(7),
+                RandomForestConfig::SubSample(0.5),
+            ],
+        );
+        forest.fit(&x, &y);
+        let mut expected = DataFrame::zeros((1, 500));
+        for tree in &forest.learners {
+            expected += &tree.predict(&x);
+        }
+        expected /= 7.0;
+        let pred = forest.predict(&x);
+        for (a, b) in pred.iter().zip(expected.iter()) {
+            assert!((a - b).abs() < 1e-3, "{} {}", a, b);
+        }
+        assert_eq!(pred[[0, 321]], forest.predict_row(x.row(321)));
+    }
+
+    /// Counts the fits running at once and the frames predicted, predicts 0
+    #[derive(Clone, Default)]
+    struct CountingLearner {
+        counters: std::sync::Arc<[std::sync::atomic::AtomicUsize; 3]>,
+    }
+
+    impl CountingLearner {
+        const RUNNING: usize = 0;
+        const PEAK: usize = 1;
+        const PREDICTS: usize = 2;
+
+        fn count(&self, counter: usize) -> usize {
+            self.counters[counter].load(std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    impl Learner for CountingLearner {
+        fn fit_weighted(&mut self, _x: &DataFrame, _y: &DataFrame, _w: &DataFrame) {
+            use std::sync::atomic::Ordering::SeqCst;
+            let running = self.counters[Self::RUNNING].fetch_add(1, SeqCst) + 1;
+            self.counters[Self::PEAK].fetch_max(running, SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            self.counters[Self::RUNNING].fetch_sub(1, SeqCst);
         }
 
-        result_sum / self.learners.len() as V
+        fn predict(&self, df: &DataFrame) -> DataFrame {
+            self.counters[Self::PREDICTS].fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            DataFrame::zeros((1, df.rows()))
+        }
+
+        fn predict_row(&self, _row: ArrayView1<V>) -> V {
+            0.0
+        }
+    }
+
+    #[test]
+    fn trees_in_flight_are_bounded() {
+        let x = DataFrame::zeros((50, 2));
+        let y = DataFrame::zeros((1, 50));
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        for score_trees in [false, true] {
+            let learner = CountingLearner::default();
+            let mut forest = RandomForest::from_configs(
+                learner.clone(),
+                vec![
+                    RandomForestConfig::NEstimators(8),
+                    RandomForestConfig::MaxTreesInFlight(2),
+                    RandomForestConfig::ScoreTrees(score_trees),
+                ],
+            );
+            pool.install(|| forest.fit(&x, &y));
+            assert!(learner.count(CountingLearner::PEAK) <= 2);
+            // only scoring predicts the whole training set, once per tree
+            let predicts = if score_trees { 8 } else { 0 };
+            assert_eq!(predicts, learner.count(CountingLearner::PREDICTS));
+        }
     }
 }
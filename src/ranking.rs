@@ -0,0 +1,130 @@
+use crate::data_frame::*;
+use crate::loss::{sigmoid, Logistic, Loss};
+use crate::utils::numeric;
+use rayon::prelude::*;
+
+/// The first row of each query group of the given sizes, followed by the number of rows
+pub fn group_offsets(groups: &[usize]) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(groups.len() + 1);
+    offsets.push(0);
+    for size in groups {
+        offsets.push(offsets.last().unwrap() + size);
+    }
+    offsets
+}
+
+/// `2^relevance - 1`
+fn gain(relevance: V) -> f64 {
+    2f64.powf(relevance as f64) - 1.0
+}
+
+/// The discount of the 0-based `rank`, `1 / log2(rank + 2)`
+fn discount(rank: usize) -> f64 {
+    1.0 / ((rank + 2) as f64).log2()
+}
+
+/// The rows `start..end` ordered by decreasing `score`, ties by row
+fn ranked(score: impl Fn(usize) -> V, start: usize, end: usize) -> Vec<usize> {
+    let mut rows: Vec<usize> = (start..end).collect();
+    rows.sort_by(|a, b| numeric::float_cmp(score(*b), score(*a)).then(a.cmp(b)));
+    rows
+}
+
+/// The DCG of the first `k` rows of `ranking` with the `relevance` of each row
+fn dcg(ranking: &[usize], relevance: impl Fn(usize) -> V, k: usize) -> f64 {
+    ranking
+        .iter()
+        .take(k)
+        .enumerate()
+        .map(|(rank, row)| gain(relevance(*row)) * discount(rank))
+        .sum()
+}
+
+/// NDCG@k of each query group: the DCG of its first `k` rows ranked by `pred` over the
+/// best possible one. Groups without relevant rows score 1.
+pub fn ndcg_at_k(y: &DataFrame, pred: &DataFrame, groups: &[usize], k: usize) -> Vec<V> {
+    assert_eq!(y.shape(), pred.shape());
+    let offsets = group_offsets(groups);
+    assert_eq!(y.cols(), *offsets.last().unwrap(), "groups must cover every row");
+    offsets
+        .windows(2)
+        .map(|group| {
+            let relevance = |row: usize| y[[0, row]];
+            let ideal = dcg(&ranked(relevance, group[0], group[1]), relevance, k);
+            if ideal > 0.0 {
+                let ranking = ranked(|row| pred[[0, row]], group[0], group[1]);
+                (dcg(&ranking, relevance, k) / ideal) as V
+            } else {
+                1.0
+            }
+        })
+        .collect()
+}
+
+/// The LambdaMART pairs of every query group, in parallel over groups: each pair of rows
+/// of a group with different labels as `(more relevant, less relevant, weight)`. The
+/// weight is the |ΔNDCG| of swapping both rows in the ranking by `scores`, times the
+/// mean sample weight of the rows.
+pub fn lambda_pairs(
+    y: &DataFrame,
+    scores: &DataFrame,
+    w: &DataFrame,
+    groups: &[usize],
+) -> Vec<(usize, usize, V)> {
+    let offsets = group_offsets(groups);
+    offsets
+        .par_windows(2)
+        .flat_map_iter(|group| {
+            let relevance = |row: usize| y[[0, row]];
+            let ideal = dcg(&ranked(relevance, group[0], group[1]), relevance, usize::MAX);
+            let ranking = ranked(|row| scores[[0, row]], group[0], group[1]);
+            let mut pairs = vec![];
+            if ideal <= 0.0 {
+                return pairs;
+            }
+            for (rank_a, a) in ranking.iter().enumerate() {
+                for (rank_b, b) in ranking.iter().enumerate().skip(rank_a + 1) {
+                    let (high, low) = match numeric::float_cmp(y[[0, *a]], y[[0, *b]]) {
+                        std::cmp::Ordering::Greater => (*a, *b),
+                        std::cmp::Ordering::Less => (*b, *a),
+                        std::cmp::Ordering::Equal => continue,
+                    };
+                    let delta = (gain(y[[0, high]]) - gain(y[[0, low]])).abs()
+                        * (discount(rank_a) - discount(rank_b)).abs()
+                        / ideal;
+                    let weight = (w[[0, high]] + w[[0, low]]) / 2.0;
+                    pairs.push((high, low, delta as V * weight));
+                }
+            }
+            pairs
+        })
+        .collect()
+}
+
+/// The weighted pairwise logistic loss `log(1 + exp(score(low) - score(high)))` of `pairs`,
+/// whose gradients are the lambdas
+pub fn pairwise_loss(pairs: &[(usize, usize, V)], score: impl Fn(usize) -> V) -> f64 {
+    pairs
+        .iter()
+        .map(|(high, low, weight)| (weight * Logistic.loss(0.0, score(*low) - score(*high))) as f64)
+        .sum()
+}
+
+/// The gradient and hessian of `pairwise_loss` in the score of each of the `samples` rows
+pub fn lambdas(
+    pairs: &[(usize, usize, V)],
+    scores: &DataFrame,
+    samples: usize,
+) -> (Vec<V>, Vec<V>) {
+    let mut gradient = vec![0.0; samples];
+    let mut hessian = vec![0.0; samples];
+    for (high, low, weight) in pairs {
+        // the probability of ranking `low` above `high`
+        let rho = sigmoid(scores[[0, *low]] - scores[[0, *high]]);
+        gradient[*high] -= weight * rho;
+        gradient[*low] += weight * rho;
+        hessian[*high] += weight * rho * (1.0 - rho);
+        hessian[*low] += weight * rho * (1.0 - rho);
+    }
+    (gradient, hessian)
+}
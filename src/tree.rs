@@ -1,18 +1,26 @@
+use crate::categorical::*;
+use crate::criterion::*;
 use crate::data_frame;
+use crate::histogram::*;
 use crate::learner::*;
 use crate::tree::NodeInfo::Stem;
+use crate::utils::binning::*;
 use crate::utils::numeric::*;
+use crate::utils::random::*;
 use crate::utils::sort_array::*;
 use data_frame::*;
 use log::*;
+use ndarray::ArrayView1;
 use num_traits::*;
 use rand::prelude::*;
 use serde::{Serialize, Deserialize};
 
 use rayon::prelude::*;
 use std::cmp::Ordering;
+use std::ops::Range;
 
 use std::collections::*;
+use std::hash::{Hash, Hasher};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DecisionTree {
@@ -28,15 +36,130 @@ pub struct DecisionTree {
     pub min_samples_leaf: usize,
     /// The number of bins feature values will be bucketed into
     pub max_bin: usize,
+    /// The algorithm used to search for splits
+    #[serde(default)]
+    pub method: TreeMethod,
+    /// The impurity splits minimize
+    #[serde(default)]
+    pub criterion: Criterion,
+    /// The number of classes of a classification tree, 0 for regression
+    #[serde(default)]
+    pub n_classes: usize,
+    /// Weights of the classes of a classification tree
+    #[serde(default)]
+    pub class_weight: ClassWeight,
+    /// Indexes of the categorical features, their values are category codes `0, 1, 2...`
+    #[serde(default)]
+    pub categorical: Vec<usize>,
+    /// Categorical nodes with at most this many categories try every prefix of the categories
+    /// sorted by the mean of their labels, nodes with more try each category alone
+    #[serde(default = "default_max_categories_sorted")]
+    pub max_categories_sorted: usize,
+    /// Complexity parameter of minimal cost-complexity pruning, 0 disables pruning
+    #[serde(default)]
+    pub ccp_alpha: V,
+    /// The order leaves are split in
+    #[serde(default)]
+    pub grow_policy: GrowPolicy,
+    /// Maximum number of leaves of the tree
+    #[serde(default = "unlimited")]
+    pub max_leaves: usize,
+    /// Direction predictions must follow in each feature: 1 non-decreasing, -1 non-increasing,
+    /// 0 (or missing) unconstrained
+    #[serde(default)]
+    pub monotone_constraints: Vec<i8>,
+    /// Groups of features allowed to interact: the features split on along a path from the
+    /// root must all belong to one group, a feature in no group only interacts with itself.
+    /// Empty lets every feature interact.
+    #[serde(default)]
+    pub interaction_constraints: Vec<Vec<usize>>,
+    /// Seed of the feature sampling, a random one is drawn at each fit if `None`
+    #[serde(default)]
+    pub random_state: Option<u64>,
 }
 
-#[derive(PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+/// The default of limits that are off unless configured
+fn unlimited() -> usize {
+    usize::MAX
+}
+
+fn default_max_categories_sorted() -> usize {
+    64
+}
+
+#[derive(PartialEq, Clone, Serialize, Deserialize)]
 pub enum DecisionTreeConfig {
     MaxDepth(usize),
     MaxFeatures(usize),
     MinSamplesSplit(usize),
     MinSamplesLeaf(usize),
     MaxBin(usize),
+    Method(TreeMethod),
+    Criterion(Criterion),
+    /// The number of classes, inferred from the labels if they hold a larger class index
+    Classes(usize),
+    ClassWeight(ClassWeight),
+    /// Indexes of the categorical features
+    Categorical(Vec<usize>),
+    /// The most categories a node splits by sorted subsets rather than one-vs-rest
+    MaxCategoriesSorted(usize),
+    /// Prunes the grown tree with minimal cost-complexity pruning
+    CcpAlpha(V),
+    GrowPolicy(GrowPolicy),
+    MaxLeaves(usize),
+    /// The monotone direction of each feature
+    MonotoneConstraints(Vec<i8>),
+    /// Groups of features allowed to interact
+    InteractionConstraints(Vec<Vec<usize>>),
+    RandomState(u64),
+}
+
+// A config holds at most one item of each kind, so the kind is enough to hash
+impl Eq for DecisionTreeConfig {}
+
+impl Hash for DecisionTreeConfig {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+    }
+}
+
+/// How the samples of each class are weighted when growing a classification tree
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub enum ClassWeight {
+    /// Every sample weighs 1
+    #[default]
+    Uniform,
+    /// Samples of class `k` weigh `samples / (n_classes * count_k)`,
+    /// so every class has the same total weight
+    Balanced,
+    /// Samples of class `k` weigh `weights[k]`, missing classes weigh 1
+    Given(Vec<V>),
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+pub enum TreeMethod {
+    /// Split points are the boundaries of `max_bin` bins found by a weighted quantile
+    /// sketch of each feature once per tree, they are searched on the globally
+    /// sorted feature orders
+    #[default]
+    Approx,
+    /// Features are quantized once into at most `max_bin` bins and splits are
+    /// searched on per-node label histograms, LightGBM style
+    Hist,
+    /// Every threshold between two distinct values is searched on the globally
+    /// sorted feature orders, `max_bin` is ignored
+    Exact,
+}
+
+/// The order the leaves of a tree are split in
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+pub enum GrowPolicy {
+    /// Every leaf of a level is split before the next level, XGBoost style
+    #[default]
+    DepthWise,
+    /// The leaf whose split decreases the impurity the most is split first, LightGBM style.
+    /// Bounded by both `max_leaves` and `max_depth`.
+    LeafWise,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -51,34 +174,170 @@ pub enum NodeInfo<V> {
         left: usize,
         /// The right child
         right: usize,
+        /// Whether missing values go to the left child
+        #[serde(default)]
+        default_left: bool,
+    },
+    /// A split of a categorical feature
+    CategoryStem {
+        /// The index of the feature of the split
+        feature: usize,
+        /// The categories going to the left child, other values go right
+        categories: CategorySet,
+        /// The left child
+        left: usize,
+        /// The right child
+        right: usize,
+        /// Whether missing values go to the left child
+        default_left: bool,
     },
 }
 
+impl NodeInfo<V> {
+    /// Whether a sample whose split feature is `row_val` goes to the left child,
+    /// training partitions samples with the same rule prediction routes them with
+    #[inline]
+    pub fn goes_left(&self, row_val: V) -> bool {
+        match self {
+            Stem {
+                param,
+                default_left,
+                ..
+            } => {
+                if row_val.is_nan() {
+                    *default_left
+                } else {
+                    row_val <= *param
+                }
+            }
+            NodeInfo::CategoryStem {
+                categories,
+                default_left,
+                ..
+            } => {
+                if row_val.is_nan() {
+                    *default_left
+                } else {
+                    categories.contains_value(row_val)
+                }
+            }
+            NodeInfo::Leaf => panic!("a leaf has no children"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TreeNode<V> {
+    /// The label mean of a regression node or the majority class of a classification node
     pub value: V,
     pub index: usize,
     pub depth: usize,
+    /// The impurity of the node
     pub variance: V,
+    /// The total weight of the node's training samples
+    #[serde(default)]
+    pub weight: V,
+    /// The weighted fraction of each class in the node, empty for regression
+    #[serde(default)]
+    pub distribution: Vec<V>,
+    /// The bounds set by monotone constraints the node's value stays within, `None` for
+    /// trees without constraints
+    #[serde(default)]
+    pub bounds: Option<(V, V)>,
     pub info: NodeInfo<V>,
 }
 
-/// Used to create thread-safe parallel split by node
-struct SplitInfo<V> {
-    /// The index of the node to be split
+/// A leaf waiting to be split, it owns the samples in `range` of the partitioned index buffers
+struct OpenNode {
+    /// The index of the node in the tree
     pub index: usize,
-    /// The new stem the split node about to become
-    pub new_stem: NodeInfo<V>,
-    /// The new variance of the current node
-    pub new_variance: V,
-    /// The left node object
-    pub left_node: TreeNode<V>,
-    /// The right node object
-    pub right_node: TreeNode<V>,
-    /// The indexes of samples to be in the left node
-    pub left_indexes: HashSet<usize>,
-    /// The indexes of samples to be in the right node
-    pub right_indexes: HashSet<usize>,
+    /// The position of the node's samples in the index buffers
+    pub range: Range<usize>,
+    /// Label statistics of the node's samples
+    pub stats: NodeStats,
+    /// Proxy impurity of the node left unsplit, splits must score lower
+    pub proxy: V,
+    /// Label histogram of the node's samples, only built in histogram mode
+    pub hist: Option<Histogram>,
+    /// Lower and upper bounds of the values in the node's subtree set by monotone constraints
+    pub bounds: (V, V),
+    /// The features split on from the root to the node, sorted
+    pub path_features: Vec<usize>,
+}
+
+/// The best split of a node on a single feature
+struct FeatureSplit {
+    pub feature: usize,
+    /// The value of the split
+    pub param: V,
+    /// The categories going to the left child of a categorical split, `param` is then unused
+    pub categories: Option<CategorySet>,
+    /// Whether missing values go to the left child
+    pub default_left: bool,
+    /// Proxy impurity of the split, see `SplitCriterion`
+    pub impurity: V,
+}
+
+impl FeatureSplit {
+    /// The stem the split node becomes, children are set when they are added to the tree
+    fn stem(&self) -> NodeInfo<V> {
+        match &self.categories {
+            Some(categories) => NodeInfo::CategoryStem {
+                feature: self.feature,
+                categories: categories.clone(),
+                left: 0,
+                right: 0,
+                default_left: self.default_left,
+            },
+            None => Stem {
+                feature: self.feature,
+                param: self.param,
+                left: 0,
+                right: 0,
+                default_left: self.default_left,
+            },
+        }
+    }
+}
+
+/// The samples a tree is grown on
+#[derive(Clone, Copy)]
+struct TrainData<'a> {
+    df: &'a DataFrame,
+    labels: &'a DataFrame,
+    /// The weight of each sample
+    weights: &'a [V],
+    /// The label and weight of each sample side by side, read while scanning sorted orders
+    targets: &'a [(V, V)],
+    /// `df` quantized into bins, `None` for exact split finding
+    binned: Option<&'a BinnedFrame>,
+    /// The seed the random stream of each node is derived from
+    seed: u64,
+}
+
+/// Used to create thread-safe parallel split by node
+struct SplitInfo {
+    /// The node to be split
+    pub node: OpenNode,
+    /// The chosen split
+    pub split: FeatureSplit,
+}
+
+impl SplitInfo {
+    /// The decrease of impurity of the split, comparable across nodes
+    fn gain(&self) -> V {
+        self.node.proxy - self.split.impurity
+    }
+}
+
+/// The index buffers the samples of open nodes are partitioned in while growing a tree
+struct Buffers {
+    /// Sample indexes, every node owns a contiguous range
+    samples: Vec<usize>,
+    /// Sample indexes sorted by each feature, partitioned like `samples`
+    feature_order: Vec<Vec<usize>>,
+    /// Marks the samples going to left children of the current splits
+    goes_left: Vec<bool>,
 }
 
 // Parallel stuff
@@ -88,18 +347,34 @@ unsafe impl<V: Send + Sync> Sync for TreeNode<V> {}
 unsafe impl<V: Send + Sync> Send for TreeNode<V> {}
 unsafe impl Sync for DecisionTree {}
 unsafe impl Send for DecisionTree {}
-unsafe impl<V: Send + Sync> Sync for SplitInfo<V> {}
-unsafe impl<V: Send + Sync> Send for SplitInfo<V> {}
+
+impl Default for DecisionTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl DecisionTree {
     pub fn new() -> Self {
         Self {
             nodes: Vec::new(),
-            max_depth: std::usize::MAX,
-            max_features: std::usize::MAX,
+            max_depth: usize::MAX,
+            max_features: usize::MAX,
             min_samples_leaf: 1,
             min_samples_split: 3,
             max_bin: 255,
+            method: TreeMethod::Approx,
+            criterion: Criterion::Mse,
+            n_classes: 0,
+            class_weight: ClassWeight::Uniform,
+            categorical: vec![],
+            max_categories_sorted: default_max_categories_sorted(),
+            ccp_alpha: 0.0,
+            grow_policy: GrowPolicy::DepthWise,
+            max_leaves: usize::MAX,
+            monotone_constraints: vec![],
+            interaction_constraints: vec![],
+            random_state: None,
         }
     }
 
@@ -115,312 +390,1506 @@ impl DecisionTree {
                 DecisionTreeConfig::MinSamplesSplit(s) => tree.min_samples_split = s,
                 DecisionTreeConfig::MinSamplesLeaf(s) => tree.min_samples_leaf = s,
                 DecisionTreeConfig::MaxBin(bin) => tree.max_bin = bin,
+                DecisionTreeConfig::Method(m) => tree.method = m,
+                DecisionTreeConfig::Criterion(c) => tree.criterion = c,
+                DecisionTreeConfig::Classes(n) => tree.n_classes = n,
+                DecisionTreeConfig::ClassWeight(w) => tree.class_weight = w,
+                DecisionTreeConfig::Categorical(features) => tree.categorical = features,
+                DecisionTreeConfig::MaxCategoriesSorted(n) => tree.max_categories_sorted = n,
+                DecisionTreeConfig::CcpAlpha(alpha) => tree.ccp_alpha = alpha,
+                DecisionTreeConfig::GrowPolicy(p) => tree.grow_policy = p,
+                DecisionTreeConfig::MaxLeaves(l) => tree.max_leaves = l,
+                DecisionTreeConfig::MonotoneConstraints(c) => tree.monotone_constraints = c,
+                DecisionTreeConfig::InteractionConstraints(groups) => {
+                    tree.interaction_constraints = groups
+                }
+                DecisionTreeConfig::RandomState(seed) => tree.random_state = Some(seed),
             }
         }
         tree
     }
 
-    fn build_model(&mut self, feature_order: Vec<Vec<usize>>, df: &DataFrame, labels: &DataFrame) {
-        let stats_info = StatsFeature::from_df_cols(&df);
-        let root = TreeNode {
-            value: stats_info.cols_mean[stats_info.cols_mean.len() - 1],
+    /// Returns the index of the leaf each row of `df` falls into
+    pub fn apply(&self, df: &DataFrame) -> Vec<usize> {
+        if self.nodes.is_empty() {
+            panic!("Model is not trained!");
+        }
+        (0..df.rows())
+            .into_par_iter()
+            .map(|row_index| self.leaf_of(df.row(row_index)))
+            .collect()
+    }
+
+    /// The index of the leaf `row` falls into
+    #[inline]
+    pub fn leaf_of(&self, row: ArrayView1<V>) -> usize {
+        // start from root
+        let mut current_node = 0;
+        while let Stem {
+            ref feature,
+            ref left,
+            ref right,
+            ..
+        }
+        | NodeInfo::CategoryStem {
+            ref feature,
+            ref left,
+            ref right,
+            ..
+        } = self.nodes[current_node].info
+        {
+            let row_val = row[*feature];
+            if self.nodes[current_node].info.goes_left(row_val) {
+                current_node = *left;
+            } else {
+                current_node = *right;
+            }
+        }
+        current_node
+    }
+
+    /// The class probabilities of each row of `df` as `[classes, samples]`,
+    /// only available for classification trees
+    pub fn predict_proba(&self, df: &DataFrame) -> DataFrame {
+        if !self.criterion.is_classification() {
+            panic!("predict_proba needs a classification criterion");
+        }
+        let mut proba = DataFrame::zeros((self.n_classes, df.rows()));
+        for (row, leaf) in self.apply(df).into_iter().enumerate() {
+            for (class, p) in self.nodes[leaf].distribution.iter().enumerate() {
+                proba[[class, row]] = *p;
+            }
+        }
+        proba
+    }
+
+    /// Sets the number of classes from the labels and returns the class weight of every sample
+    fn sample_weights(&mut self, labels: &DataFrame) -> Vec<V> {
+        if !self.criterion.is_classification() {
+            self.n_classes = 0;
+            return vec![1.0; labels.cols()];
+        }
+        for y in labels.row(0) {
+            if *y < 0.0 || y.fract() != 0.0 {
+                panic!("Classification labels must be class indexes, found {}", y);
+            }
+        }
+        let max_class = labels.row(0).iter().fold(0, |max, y| max.max(*y as usize));
+        self.n_classes = self.n_classes.max(max_class + 1);
+
+        let class_weights: Vec<V> = match &self.class_weight {
+            ClassWeight::Uniform => vec![1.0; self.n_classes],
+            ClassWeight::Balanced => {
+                let mut counts = vec![0usize; self.n_classes];
+                for y in labels.row(0) {
+                    counts[*y as usize] += 1;
+                }
+                counts
+                    .iter()
+                    .map(|count| labels.cols() as V / (self.n_classes * count.max(&1)) as V)
+                    .collect()
+            }
+            ClassWeight::Given(weights) => (0..self.n_classes)
+                .map(|class| weights.get(class).copied().unwrap_or(1.0))
+                .collect(),
+        };
+        labels.row(0).iter().map(|y| class_weights[*y as usize]).collect()
+    }
+
+    /// A leaf holding the samples summarized by `stats`, `targets` are their labels and weights
+    fn new_node(&self, stats: &NodeStats, targets: &[(V, V)], depth: usize) -> TreeNode<V> {
+        let criterion = self.criterion.node_criterion();
+        TreeNode {
+            value: criterion.leaf_value(stats, targets),
             index: 0,
-            depth: 0,
-            variance: col_variance(labels),
+            depth,
+            variance: criterion.node_impurity(stats, targets),
+            weight: stats.weight as V,
+            distribution: stats.class_distribution(),
+            bounds: None,
             info: NodeInfo::Leaf,
+        }
+    }
+
+    /// The leaf and the open node of the samples in `range` of `samples`
+    fn new_open_node(
+        &self,
+        samples: &[usize],
+        range: Range<usize>,
+        depth: usize,
+        hist: Option<Histogram>,
+        bounds: (V, V),
+        data: TrainData,
+    ) -> (TreeNode<V>, OpenNode) {
+        let stats = NodeStats::from_samples(&samples[range.clone()], data.labels, data.weights, self.n_classes);
+        let targets: Vec<(V, V)> = samples[range.clone()]
+            .iter()
+            .map(|i| data.targets[*i])
+            .collect();
+        let mut node = self.new_node(&stats, &targets, depth);
+        if !self.monotone_constraints.is_empty() {
+            node.value = node.value.clamp(bounds.0, bounds.1);
+            node.bounds = Some(bounds);
+        }
+        let proxy = match self.criterion.scoring() {
+            Scoring::Proxy(criterion) => {
+                criterion.proxy_impurity(&stats, &NodeStats::new(self.n_classes))
+            }
+            Scoring::Ordered(_) => node.variance,
+        };
+        let open = OpenNode {
+            index: 0,
+            range,
+            stats,
+            proxy,
+            hist,
+            bounds,
+            path_features: vec![],
         };
+        (node, open)
+    }
 
-        self.nodes.push(root);
+    /// The monotone direction of `feature`
+    fn monotone(&self, feature: usize) -> i8 {
+        self.monotone_constraints.get(feature).copied().unwrap_or(0)
+    }
 
-        //records at each tree node by index, data will be dropped after use to save memory
-        let mut node_sample_index = HashMap::<usize, HashSet<usize>>::new();
-        //Root node contains all records
-        node_sample_index.insert(0, (0..df.rows()).collect());
+    /// The features a node may be split on given the features split on above it: those
+    /// of the interaction groups holding every feature of the path
+    fn allowed_features(&self, path_features: &[usize], n_features: usize) -> Vec<usize> {
+        if self.interaction_constraints.is_empty() || path_features.is_empty() {
+            return (0..n_features).collect();
+        }
+        let mut allowed = vec![false; n_features];
+        for feature in path_features {
+            allowed[*feature] = true;
+        }
+        for group in &self.interaction_constraints {
+            if path_features.iter().all(|f| group.contains(f)) {
+                for feature in group.iter().filter(|f| **f < n_features) {
+                    allowed[*feature] = true;
+                }
+            }
+        }
+        (0..n_features).filter(|f| allowed[*f]).collect()
+    }
 
-        //contains indexes to nodes to be split
-        let mut current_nodes: Vec<usize> = vec![0];
+    /// Randomly choose at most `max_features` of the `allowed` features to look for a split,
+    /// the random stream of a node only depends on its index
+    fn sample_features(&self, mut features: Vec<usize>, node: usize, seed: u64) -> Vec<usize> {
+        if features.len() <= self.max_features {
+            return features;
+        }
+        let mut rng = seeded_rng(derive_seed(seed, node as u64));
 
-        // Loop till the tree has no nodes to be further split
-        // Each loop will grow another level in the resulting tree
-        // a node stop being split when it reaches the max depth
-        // or its number of records is lower than min_sample_split
-        loop {
-            if current_nodes.is_empty() {
-                break;
+        features.shuffle(&mut rng);
+        // only take the max_features number of features from the list
+        features.truncate(self.max_features);
+        features
+    }
+
+    /// Turns the node at `index` into `stem` and appends its two children,
+    /// returns the indexes of the new left and right node
+    fn attach_children(
+        &mut self,
+        index: usize,
+        stem: NodeInfo<V>,
+        mut left_node: TreeNode<V>,
+        mut right_node: TreeNode<V>,
+    ) -> (usize, usize) {
+        let curr_nodes_len = self.nodes.len();
+
+        // Update the split node, it keeps its impurity as a leaf for pruning
+        self.nodes[index].info = stem;
+
+        debug!(
+            "variance: {} {}\n value: {} {}",
+            left_node.variance, right_node.variance, left_node.value, right_node.value
+        );
+        // add Left child
+        left_node.index = curr_nodes_len;
+        self.nodes.push(left_node);
+        // add Right Child
+        right_node.index = curr_nodes_len + 1;
+        self.nodes.push(right_node);
+        debug!("Added {} {}", curr_nodes_len, curr_nodes_len + 1);
+
+        //set left and right for parent node
+        match self.nodes[index].info {
+            NodeInfo::Stem {
+                ref feature,
+                ref param,
+                ref mut left,
+                ref mut right,
+                ..
+            } => {
+                debug!("split on {}: {}", feature, param);
+                *left = curr_nodes_len;
+                *right = curr_nodes_len + 1;
             }
+            NodeInfo::CategoryStem {
+                ref feature,
+                ref mut left,
+                ref mut right,
+                ..
+            } => {
+                debug!("split on categories of {}", feature);
+                *left = curr_nodes_len;
+                *right = curr_nodes_len + 1;
+            }
+            NodeInfo::Leaf => panic!("expected stem as parent node, found leaf"),
+        }
+        (curr_nodes_len, curr_nodes_len + 1)
+    }
 
-            // Parallel perform split for current leaf nodes
-            // Get split info about the current node and two children
-            let next_nodes_pairs: Vec<Option<SplitInfo<V>>> = current_nodes
-                .par_iter()
-                .map(|node_index| {
-                    // Stop split when reach max depthor
-                    if self.nodes[*node_index].depth >= self.max_depth {
-                        return None;
-                    }
+    /// Search the node's samples sorted by `feature` for the best split, at the bin
+    /// boundaries of `feature` if `binned` is given or else between every two distinct values.
+    /// Label statistics of the left child are accumulated while scanning and the right
+    /// child's are derived from the node's `total`, so each split point costs constant time.
+    /// Missing values are sorted last, every split tries sending them to either child.
+    fn find_sorted_split(
+        &self,
+        feature: usize,
+        order: &[usize],
+        total: &NodeStats,
+        data: TrainData,
+    ) -> Option<FeatureSplit> {
+        let TrainData {
+            df,
+            targets,
+            binned,
+            ..
+        } = data;
+        let present = order.partition_point(|i| !df[[*i, feature]].is_nan());
+        let mut missing = NodeStats::new(self.n_classes);
+        for index in &order[present..] {
+            let (y, weight) = targets[*index];
+            missing.add(y, weight);
+        }
+        // whether a split may fall between the `pos - 1`th and `pos`th samples,
+        // splitting after the last present value only separates missing values
+        let boundary = |pos: usize| {
+            if pos == present {
+                return missing.count > 0;
+            }
+            match binned {
+                Some(binned) => {
+                    let codes = &binned.codes[feature];
+                    codes.get(order[pos - 1]) != codes.get(order[pos])
+                }
+                None => df[[order[pos - 1], feature]] != df[[order[pos], feature]],
+            }
+        };
 
-                    let curr_samples = node_sample_index.get(node_index).unwrap();
-                    // No split when there are not many samples in node or
-                    // samples can not be divided to bins
-                    if curr_samples.len() < self.min_samples_split
+        // (position, impurity, whether missing values go left)
+        let mut best: Option<(usize, V, bool)> = None;
+        let mut update = |pos: usize, impurity: V, default_left: bool| {
+            if best.is_none_or(|(_, best_impurity, _)| impurity < best_impurity) {
+                best = Some((pos, impurity, default_left));
+            }
+        };
+        match self.criterion.scoring() {
+            Scoring::Ordered(criterion) => {
+                let present_targets: Vec<(V, V)> =
+                    order[..present].iter().map(|i| targets[*i]).collect();
+                let missing_targets: Vec<(V, V)> =
+                    order[present..].iter().map(|i| targets[*i]).collect();
+                best = self.find_order_split(criterion, present_targets, &missing_targets, boundary);
+            }
+            Scoring::Proxy(criterion) => {
+                let mut left = NodeStats::new(self.n_classes);
+                for pos in 1..=present {
+                    let (y, weight) = targets[order[pos - 1]];
+                    left.add(y, weight);
+                    if !boundary(pos) {
+                        continue;
+                    }
+                    if let Some((impurity, default_left)) =
+                        score_split(
+                            criterion,
+                            total,
+                            &left,
+                            &missing,
+                            self.min_samples_leaf,
+                            self.monotone(feature),
+                        )
                     {
-                        return None;
+                        update(pos, impurity, default_left);
                     }
+                }
+            }
+        }
 
-                    let curr_bins = self.max_bin.min(curr_samples.len());
+        best.map(|(pos, impurity, default_left)| FeatureSplit {
+            feature,
+            param: match binned {
+                _ if pos == present => V::INFINITY,
+                Some(binned) => binned.mappers[feature]
+                    .threshold(binned.codes[feature].get(order[pos - 1])),
+                None => split_midpoint(df[[order[pos - 1], feature]], df[[order[pos], feature]]),
+            },
+            categories: None,
+            default_left,
+            impurity,
+        })
+    }
 
-                    // local thread Random generator
-                    let mut rng = rand::thread_rng();
+    /// Search the best subset of categories of a categorical `feature` to send left,
+    /// see `find_category_split`. Samples of a category are contiguous in the sorted order
+    /// of the feature and bins of a categorical feature are its categories.
+    fn find_categorical_split(
+        &self,
+        feature: usize,
+        node: &OpenNode,
+        feature_order: &[Vec<usize>],
+        data: TrainData,
+    ) -> Option<FeatureSplit> {
+        let criterion = match self.criterion.scoring() {
+            Scoring::Proxy(criterion) => criterion,
+            // ordered criteria never use histograms
+            Scoring::Ordered(criterion) => {
+                let order = &feature_order[feature][node.range.clone()];
+                return self.find_categorical_order_split(criterion, feature, order, data);
+            }
+        };
+        let (categories, missing): (Vec<CategoryStats>, NodeStats) = match &node.hist {
+            Some(hist) => {
+                let (bins, missing) = hist.features[feature].split_at(hist.features[feature].len() - 1);
+                let categories = bins
+                    .iter()
+                    .enumerate()
+                    .map(|(category, stats)| CategoryStats {
+                        category,
+                        stats: stats.clone(),
+                    })
+                    .collect();
+                (categories, missing[0].clone())
+            }
+            None => {
+                let order = &feature_order[feature][node.range.clone()];
+                let (present, ranges) = category_ranges(data.df, feature, order);
+                let stats_of = |indexes: &[usize]| {
+                    NodeStats::from_samples(indexes, data.labels, data.weights, self.n_classes)
+                };
+                let categories = ranges
+                    .into_iter()
+                    .map(|(category, range)| CategoryStats {
+                        category,
+                        stats: stats_of(&order[range]),
+                    })
+                    .collect();
+                (categories, stats_of(&order[present..]))
+            }
+        };
 
-                    // split a node
-                    let mut features: Vec<usize> = (0..df.cols()).collect();
-                    features.shuffle(&mut rng);
-                    // only take the max_features number of features from the list
-                    features.resize(self.max_features.min(df.cols()), 0);
+        // many categories are tried one against the others, sorted subsets would overfit them
+        let present = categories.iter().filter(|c| c.stats.count > 0).count();
+        let one_vs_rest = present > self.max_categories_sorted;
+        let (left, impurity, default_left) = find_category_split(
+            criterion,
+            categories,
+            &missing,
+            &node.stats,
+            self.min_samples_leaf,
+            one_vs_rest,
+        )?;
+        Some(FeatureSplit {
+            feature,
+            param: 0.0,
+            categories: Some(left),
+            default_left,
+            impurity,
+        })
+    }
 
-                    let bin_size = curr_samples.len() / curr_bins;
-                    // split points of bins, k bins means k-1 split points
-                    let bins: Vec<usize> = (1..curr_bins)
-                        .into_iter()
-                        .map(|b_i| bin_size * b_i)
-                        .collect();
+    /// Categorical split for criteria that need order statistics: categories are sorted by
+    /// the mean of their labels and the samples scanned in that order
+    fn find_categorical_order_split(
+        &self,
+        criterion: &dyn OrderedCriterion,
+        feature: usize,
+        order: &[usize],
+        data: TrainData,
+    ) -> Option<FeatureSplit> {
+        let (present, mut ranges) = category_ranges(data.df, feature, order);
+        let mean = |range: &Range<usize>| {
+            let (sum, weight) = order[range.clone()].iter().fold((0.0, 0.0), |(sum, weight), i| {
+                let (y, w) = data.targets[*i];
+                (sum + (y * w) as f64, weight + w as f64)
+            });
+            sum / weight
+        };
+        ranges.sort_by(|a, b| mean(&a.1).partial_cmp(&mean(&b.1)).unwrap_or(Ordering::Equal));
 
-                    // parallel perform split for each feature
-                    // get the indexes for the splitting value and the square errors of each resulting split
-                    // (feature_index, feature_split_point, variance, left_variance, right_variance, split_index)
-                    let feature_split: Vec<(usize, usize, V, V, V, usize)> = features
-                        .par_iter()
-                        .map(|feat_index| {
-                            // split position
-                            let mut best_split: usize = 0;
-                            // sum of two children's square error after split
-                            let mut best_sqr_err: V = self.nodes[*node_index].variance;
-                            let mut best_right_err: V = 0.0;
-                            let mut best_left_err: V = 0.0;
-                            let mut feat_labels = Vec::with_capacity(curr_samples.len());
-                            let orders: &Vec<usize> = &feature_order[*feat_index];
-                            let mut bins_index = vec![std::usize::MAX; curr_bins - 1];
-                            for index in orders {
-                                if curr_samples.contains(index) {
-                                    // set split index for k-1 split points
-                                    if feat_labels.len() % bin_size == 0
-                                        && feat_labels.len() != 0
-                                        && feat_labels.len() < bin_size * curr_bins
-                                    {
-                                        bins_index[feat_labels.len() / bin_size - 1] = *index;
-                                    }
-                                    feat_labels.push(labels[[0, *index]]);
-                                }
-                            }
-                            // search each bins for resulting split info
-                            for bin in &bins {
-                                let (left, right) = feat_labels.split_at(*bin);
-                                // let left_weight = *bin as V / curr_samples.len() as V;
-                                let left_sqr_err = slice_variance(left);
-                                let right_sqr_err = slice_variance(right);
-                                let curr_sqr_err = left_sqr_err + right_sqr_err;
-                                if curr_sqr_err < best_sqr_err {
-                                    best_split = *bin;
-                                    best_sqr_err = curr_sqr_err;
-                                    best_left_err = left_sqr_err;
-                                    best_right_err = right_sqr_err;
-                                }
-                            }
-                            (
-                                *feat_index,
-                                best_split,
-                                best_sqr_err,
-                                best_left_err,
-                                best_right_err,
-                                // don't panic when this if there is no split!
-                                if best_split == 0 {
-                                    1
-                                } else {
-                                    bins_index[best_split / bin_size - 1]
-                                },
-                            )
+        let present_targets: Vec<(V, V)> = ranges
+            .iter()
+            .flat_map(|(_, range)| order[range.clone()].iter().map(|i| data.targets[*i]))
+            .collect();
+        let missing_targets: Vec<(V, V)> = order[present..].iter().map(|i| data.targets[*i]).collect();
+        // splits fall between two categories
+        let mut ends = HashSet::new();
+        let mut end = 0;
+        for (_, range) in &ranges[..ranges.len().saturating_sub(1)] {
+            end += range.len();
+            ends.insert(end);
+        }
+        if !missing_targets.is_empty() {
+            ends.insert(present);
+        }
+
+        let (pos, impurity, default_left) =
+            self.find_order_split(criterion, present_targets, &missing_targets, |pos| {
+                ends.contains(&pos)
+            })?;
+        let mut left = vec![];
+        let mut end = 0;
+        for (category, range) in &ranges {
+            if end >= pos {
+                break;
+            }
+            end += range.len();
+            left.push(*category);
+        }
+        Some(FeatureSplit {
+            feature,
+            param: 0.0,
+            categories: Some(CategorySet::from_categories(&left)),
+            default_left,
+            impurity,
+        })
+    }
+
+    /// Best split of `present` targets, in the order splits are searched in, for criteria
+    /// that need order statistics. Splits may only send the first `pos` targets left where
+    /// `boundary(pos)`, and `missing` targets go to the side that scores lower.
+    /// Returns the position, the impurity and whether missing targets go left.
+    fn find_order_split(
+        &self,
+        criterion: &dyn OrderedCriterion,
+        mut present: Vec<(V, V)>,
+        missing: &[(V, V)],
+        boundary: impl Fn(usize) -> bool,
+    ) -> Option<(usize, V, bool)> {
+        let min_samples_leaf = self.min_samples_leaf.max(1);
+        // impurities of every left child, and of every right child scanning backwards,
+        // with and without the missing values
+        let prefix = |targets: &[(V, V)], with_missing: bool| {
+            if with_missing {
+                criterion.prefix_impurities(&[missing, targets].concat())
+            } else {
+                criterion.prefix_impurities(targets)
+            }
+        };
+        let has_missing = !missing.is_empty();
+        let left = prefix(&present, false);
+        let missing_left = if has_missing { prefix(&present, true) } else { vec![] };
+        present.reverse();
+        let right = prefix(&present, false);
+        let missing_right = if has_missing { prefix(&present, true) } else { vec![] };
+
+        let mut best: Option<(usize, V, bool)> = None;
+        let mut update = |pos: usize, impurity: V, default_left: bool| {
+            if best.is_none_or(|(_, best_impurity, _)| impurity < best_impurity) {
+                best = Some((pos, impurity, default_left));
+            }
+        };
+        let n_missing = missing.len();
+        for pos in (1..=present.len()).filter(|pos| boundary(*pos)) {
+            let right_len = present.len() - pos;
+            if !has_missing {
+                if pos >= min_samples_leaf && right_len >= min_samples_leaf {
+                    update(pos, left[pos] + right[right_len], pos > right_len);
+                }
+                continue;
+            }
+            if pos + n_missing >= min_samples_leaf && right_len >= min_samples_leaf {
+                update(pos, missing_left[n_missing + pos] + right[right_len], true);
+            }
+            if pos >= min_samples_leaf && right_len + n_missing >= min_samples_leaf {
+                update(pos, left[pos] + missing_right[n_missing + right_len], false);
+            }
+        }
+        best
+    }
+
+    /// Find the split that minimize the impurity of a node,
+    /// `None` if the node should stay a leaf
+    fn find_split(
+        &self,
+        node: OpenNode,
+        feature_order: &[Vec<usize>],
+        data: TrainData,
+    ) -> Option<SplitInfo> {
+        let tree_node = &self.nodes[node.index];
+        // Stop split when reach max depth or
+        // there are not many samples in node
+        if tree_node.depth >= self.max_depth || node.range.len() < self.min_samples_split {
+            return None;
+        }
+
+        // parallel perform split for each feature
+        let split = self
+            .sample_features(
+                self.allowed_features(&node.path_features, data.df.cols()),
+                node.index,
+                data.seed,
+            )
+            .into_par_iter()
+            .filter_map(|feature| match (self.criterion.scoring(), &node.hist, data.binned) {
+                _ if self.categorical.contains(&feature) => {
+                    self.find_categorical_split(feature, &node, feature_order, data)
+                }
+                (Scoring::Proxy(criterion), Some(hist), Some(binned)) => {
+                    hist.find_split(
+                        feature,
+                        &node.stats,
+                        self.min_samples_leaf,
+                        self.monotone(feature),
+                        criterion,
+                    )
+                        .map(|split| FeatureSplit {
+                            feature,
+                            param: binned.mappers[feature].threshold(split.bin),
+                            categories: None,
+                            default_left: split.default_left,
+                            impurity: split.impurity,
                         })
-                        .collect();
+                }
+                _ => self.find_sorted_split(
+                    feature,
+                    &feature_order[feature][node.range.clone()],
+                    &node.stats,
+                    data,
+                ),
+            })
+            .min_by(|a, b| a.impurity.partial_cmp(&b.impurity).unwrap_or(Ordering::Equal))?;
 
-                    // sort splits to find the split that minimize the new variance
-                    let min_split = feature_split
-                        .iter()
-                        .min_by(|a, b| a.2.partial_cmp(&b.2).unwrap_or(Ordering::Equal));
-
-                    match min_split {
-                        None => None,
-                        Some(split) => {
-                            // Not enough to form a leaf
-                            if split.1 < self.min_samples_leaf
-                                || curr_samples.len() - split.1 < self.min_samples_leaf
-                                || split.1 == 0
-                                || curr_samples.len() - split.1 == 0
-                            {
-                                return None;
-                            }
-
-                            // samples in children after split
-                            let mut left_index = HashSet::with_capacity(split.1);
-                            let mut right_index =
-                                HashSet::with_capacity(curr_samples.len() - split.1);
-
-                            // find the split point value
-                            let orders: &Vec<usize> = &feature_order[split.0];
-                            let mut split_val: V = V::infinity();
-                            let mut index_to_write = &mut left_index;
-                            let mut right_label_sum: V = 0.0;
-                            let mut left_label_sum: V = 0.0;
-                            let mut sum_to_add = &mut left_label_sum;
-                            for index in orders {
-                                if curr_samples.contains(index) {
-                                    index_to_write.insert(*index);
-                                    *sum_to_add += labels[[0, *index]];
-                                    if *index == split.5 {
-                                        split_val = df[[*index, split.0]];
-                                        // start recording right children's samples
-                                        index_to_write = &mut right_index;
-                                        sum_to_add = &mut right_label_sum;
-                                    }
-                                }
-                            }
-
-                            // create left and right node
-                            let left_node = TreeNode {
-                                value: left_label_sum / left_index.len() as V,
-                                index: 0,
-                                depth: self.nodes[*node_index].depth + 1,
-                                variance: split.3,
-                                info: NodeInfo::Leaf,
-                            };
-                            let right_node = TreeNode {
-                                value: right_label_sum / right_index.len() as V,
-                                index: 0,
-                                depth: self.nodes[*node_index].depth + 1,
-                                variance: split.4,
-                                info: NodeInfo::Leaf,
-                            };
-
-                            Some(SplitInfo {
-                                index: *node_index,
-                                new_stem: Stem {
-                                    feature: split.0,
-                                    param: split_val,
-                                    left: 0,
-                                    right: 0,
-                                },
-                                new_variance: split.2,
-                                left_node,
-                                right_node,
-                                left_indexes: left_index,
-                                right_indexes: right_index,
-                            })
-                        }
+        // a split must decrease the impurity
+        if split.impurity >= node.proxy {
+            return None;
+        }
+        Some(SplitInfo { node, split })
+    }
+
+    /// Grows the tree following `grow_policy` until no leaf can be split or the tree has
+    /// `max_leaves` leaves. Samples of every node are kept in a contiguous range of the
+    /// index buffer and of each feature's sorted order; splitting a node stably
+    /// partitions these ranges so children own two adjacent ranges.
+    fn build_model(
+        &mut self,
+        method: TreeMethod,
+        feature_order: Vec<Vec<usize>>,
+        data: TrainData,
+    ) {
+        let TrainData {
+            df,
+            labels,
+            weights,
+            binned,
+            ..
+        } = data;
+        self.nodes.clear();
+        let samples: Vec<usize> = (0..df.rows()).collect();
+        let root_hist = match (method, binned) {
+            (TreeMethod::Hist, Some(binned)) => Some(Histogram::build(
+                binned,
+                &samples,
+                labels,
+                weights,
+                self.n_classes,
+            )),
+            _ => None,
+        };
+        let (root, root_open) = self.new_open_node(
+            &samples,
+            0..samples.len(),
+            0,
+            root_hist,
+            (V::NEG_INFINITY, V::INFINITY),
+            data,
+        );
+        self.nodes.push(root);
+
+        let mut buffers = Buffers {
+            goes_left: vec![false; samples.len()],
+            samples,
+            feature_order,
+        };
+        let mut leaves = 1;
+        match self.grow_policy {
+            GrowPolicy::DepthWise => {
+                //contains nodes to be split
+                let mut current_nodes = vec![root_open];
+
+                // Loop till the tree has no nodes to be further split
+                // Each loop will grow another level in the resulting tree
+                // a node stop being split when it reaches the max depth
+                // or its number of records is lower than min_sample_split
+                while !current_nodes.is_empty() && leaves < self.max_leaves {
+                    // Parallel perform split for current leaf nodes
+                    let mut splits: Vec<SplitInfo> = current_nodes
+                        .into_par_iter()
+                        .filter_map(|node| {
+                            self.find_split(node, &buffers.feature_order, data)
+                        })
+                        .collect();
+                    // only the best splits of the level fit in the leaf budget
+                    if splits.len() > self.max_leaves - leaves {
+                        splits.sort_by(|a, b| b.gain().total_cmp(&a.gain()));
+                        splits.truncate(self.max_leaves - leaves);
                     }
+                    leaves += splits.len();
+                    current_nodes = self.split_nodes(splits, &mut buffers, data);
+                }
+            }
+            GrowPolicy::LeafWise => {
+                // the best split of every leaf that can be split
+                let mut candidates: Vec<SplitInfo> = self
+                    .find_split(root_open, &buffers.feature_order, data)
+                    .into_iter()
+                    .collect();
+                while !candidates.is_empty() && leaves < self.max_leaves {
+                    let (best, _) = candidates
+                        .iter()
+                        .enumerate()
+                        .max_by(|(_, a), (_, b)| a.gain().total_cmp(&b.gain()))
+                        .unwrap();
+                    let split = candidates.swap_remove(best);
+                    leaves += 1;
+                    let children = self.split_nodes(vec![split], &mut buffers, data);
+                    let feature_order = &buffers.feature_order;
+                    let splits: Vec<SplitInfo> = children
+                        .into_par_iter()
+                        .filter_map(|node| self.find_split(node, feature_order, data))
+                        .collect();
+                    candidates.extend(splits);
+                }
+            }
+        }
+    }
+
+    /// Applies `splits` to the tree: moves the samples of each split node to its
+    /// children's ranges and appends the children, which are returned as open nodes
+    fn split_nodes(
+        &mut self,
+        splits: Vec<SplitInfo>,
+        buffers: &mut Buffers,
+        data: TrainData,
+    ) -> Vec<OpenNode> {
+        let TrainData {
+            df,
+            labels,
+            weights,
+            binned,
+            ..
+        } = data;
+        let Buffers {
+            samples,
+            feature_order,
+            goes_left,
+        } = buffers;
+
+        for split_info in &splits {
+            let stem = split_info.split.stem();
+            let feature = split_info.split.feature;
+            for index in &samples[split_info.node.range.clone()] {
+                goes_left[*index] = stem.goes_left(df[[*index, feature]]);
+            }
+        }
+
+        // Move the samples of each split node to its children's ranges
+        let ranges: Vec<Range<usize>> =
+            splits.iter().map(|info| info.node.range.clone()).collect();
+        let (left_lens, _) = rayon::join(
+            || partition_ranges(samples, &ranges, goes_left),
+            || {
+                feature_order.par_iter_mut().for_each(|order| {
+                    partition_ranges(order, &ranges, goes_left);
                 })
-                .collect();
+            },
+        );
+        let samples = &*samples;
 
-            // prepare for next round grow
-            node_sample_index.clear();
-            current_nodes.clear();
-
-            // Update everything from split info in serial
-            for node_pair in next_nodes_pairs {
-                if let None = node_pair {
-                    continue;
-                } else if let Some(mut split_info) = node_pair {
-                    let curr_nodes_len = self.nodes.len();
-
-                    // Update the split node
-                    self.nodes[split_info.index].variance = split_info.new_variance;
-                    self.nodes[split_info.index].info = split_info.new_stem;
-
-                    debug!(
-                        "Left: {}, right: {}, depth: {}",
-                        split_info.left_indexes.len(),
-                        split_info.right_indexes.len(),
-                        split_info.left_node.depth
-                    );
-                    debug!(
-                        "variance: {} {}\n value: {} {}",
-                        split_info.left_node.variance,
-                        split_info.right_node.variance,
-                        split_info.left_node.value,
-                        split_info.right_node.value
-                    );
-                    // add Left child
-                    split_info.left_node.index = curr_nodes_len;
-                    self.nodes.push(split_info.left_node);
-                    current_nodes.push(curr_nodes_len);
-                    node_sample_index.insert(curr_nodes_len, split_info.left_indexes);
-                    debug!("Added {} {}", curr_nodes_len, curr_nodes_len + 1);
-                    // add Right Child
-                    split_info.right_node.index = curr_nodes_len + 1;
-                    self.nodes.push(split_info.right_node);
-                    current_nodes.push(curr_nodes_len + 1);
-                    node_sample_index.insert(curr_nodes_len + 1, split_info.right_indexes);
-
-                    //set left and right for parent node
-                    if let NodeInfo::Stem {
-                        ref mut feature,
-                        ref mut param,
-                        ref mut left,
-                        ref mut right,
-                    } = self.nodes[split_info.index].info
+        // Create the children of each split node
+        let children: Vec<(TreeNode<V>, OpenNode, TreeNode<V>, OpenNode)> = splits
+            .par_iter()
+            .zip(left_lens)
+            .map(|(info, left_len)| {
+                let range = &info.node.range;
+                let depth = self.nodes[info.node.index].depth + 1;
+                let left_range = range.start..range.start + left_len;
+                let right_range = range.start + left_len..range.end;
+
+                // Only children that may be split again need histograms,
+                // the larger child's one is derived from the parent by subtraction
+                let expand = |range: &Range<usize>| {
+                    depth < self.max_depth && range.len() >= self.min_samples_split
+                };
+                let (left_hist, right_hist) = match (&info.node.hist, binned) {
+                    (Some(hist), Some(binned))
+                        if expand(&left_range) || expand(&right_range) =>
                     {
-                        debug!("split on {}: {}", feature, param);
-                        *left = curr_nodes_len;
-                        *right = curr_nodes_len + 1;
-                    } else {
-                        panic!("expected stem as parent node, found leaf")
+                        if left_range.len() <= right_range.len() {
+                            let left_hist = Histogram::build(
+                                binned,
+                                &samples[left_range.clone()],
+                                labels,
+                                weights,
+                                self.n_classes,
+                            );
+                            let right_hist = hist.subtract(&left_hist);
+                            (Some(left_hist), Some(right_hist))
+                        } else {
+                            let right_hist = Histogram::build(
+                                binned,
+                                &samples[right_range.clone()],
+                                labels,
+                                weights,
+                                self.n_classes,
+                            );
+                            let left_hist = hist.subtract(&right_hist);
+                            (Some(left_hist), Some(right_hist))
+                        }
                     }
+                    _ => (None, None),
+                };
+
+                let bounds = info.node.bounds;
+                let (mut left_node, mut left_open) =
+                    self.new_open_node(samples, left_range, depth, left_hist, bounds, data);
+                let (mut right_node, mut right_open) =
+                    self.new_open_node(samples, right_range, depth, right_hist, bounds, data);
+
+                // children of a monotone split get disjoint bounds separated by
+                // the mean of their values, so their subtrees cannot cross
+                let mid = (left_node.value + right_node.value) / 2.0;
+                match self.monotone(info.split.feature) {
+                    1 => {
+                        left_open.bounds.1 = mid.min(bounds.1);
+                        right_open.bounds.0 = mid.max(bounds.0);
+                    }
+                    -1 => {
+                        left_open.bounds.0 = mid.max(bounds.0);
+                        right_open.bounds.1 = mid.min(bounds.1);
+                    }
+                    _ => {}
                 }
-            }
+                if !self.monotone_constraints.is_empty() {
+                    left_node.bounds = Some(left_open.bounds);
+                    right_node.bounds = Some(right_open.bounds);
+                }
+                if !self.interaction_constraints.is_empty() {
+                    let mut path = info.node.path_features.clone();
+                    if let Err(pos) = path.binary_search(&info.split.feature) {
+                        path.insert(pos, info.split.feature);
+                    }
+                    left_open.path_features = path.clone();
+                    right_open.path_features = path;
+                }
+                (left_node, left_open, right_node, right_open)
+            })
+            .collect();
+
+        // Update everything from split info in serial
+        let mut open_nodes = Vec::with_capacity(children.len() * 2);
+        for (split_info, (left_node, mut left_open, right_node, mut right_open)) in
+            splits.into_iter().zip(children)
+        {
+            debug!(
+                "Left: {}, right: {}, depth: {}",
+                left_open.range.len(),
+                right_open.range.len(),
+                left_node.depth
+            );
+            let split = split_info.split;
+            let (left, right) = self.attach_children(
+                split_info.node.index,
+                split.stem(),
+                left_node,
+                right_node,
+            );
+            left_open.index = left;
+            right_open.index = right;
+            open_nodes.push(left_open);
+            open_nodes.push(right_open);
         }
+        open_nodes
     }
 }
 
 impl Learner for DecisionTree {
-    fn fit(&mut self, x: &DataFrame, y: &DataFrame) {
-        let features_order = get_df_sorted_perm(&x);
-
-        self.build_model(features_order, x, y);
+    fn fit_weighted(&mut self, x: &DataFrame, y: &DataFrame, w: &DataFrame) {
+        assert_eq!(y.shape(), w.shape());
+        if w.iter().any(|w| !(*w >= 0.0 && w.is_finite())) {
+            panic!("Sample weights must be non-negative");
+        }
+        if self.criterion == Criterion::Poisson && y.iter().any(|y| *y < 0.0) {
+            panic!("Poisson criterion needs non-negative labels");
+        }
+        // histograms only hold running sums
+        let method = match self.method {
+            TreeMethod::Hist if matches!(self.criterion.scoring(), Scoring::Ordered(_)) => {
+                warn!("{:?} criterion cannot use histograms, using Approx", self.criterion);
+                TreeMethod::Approx
+            }
+            method => method,
+        };
+        if self.monotone_constraints.iter().any(|c| *c != 0) {
+            let ordered = matches!(self.criterion.scoring(), Scoring::Ordered(_));
+            if self.criterion.is_classification() || ordered {
+                panic!("Monotone constraints need a criterion whose leaves predict the mean");
+            }
+            if self.categorical.iter().any(|f| self.monotone(*f) != 0) {
+                panic!("Categorical features cannot be monotone");
+            }
+        }
+        for feature in &self.categorical {
+            if x.column(*feature).iter().any(|v| *v < 0.0 || (!v.is_nan() && v.fract() != 0.0)) {
+                panic!("Categorical feature {} must hold category codes", feature);
+            }
+        }
+        let weights: Vec<V> = self
+            .sample_weights(y)
+            .iter()
+            .zip(w.row(0))
+            .map(|(class_weight, w)| class_weight * w)
+            .collect();
+        let targets: Vec<(V, V)> = y.row(0).iter().copied().zip(weights.iter().copied()).collect();
+        let data = TrainData {
+            df: x,
+            labels: y,
+            weights: &weights,
+            targets: &targets,
+            binned: None,
+            seed: seed_or_random(self.random_state),
+        };
+        match method {
+            TreeMethod::Exact => {
+                let features_order = get_df_sorted_perm(x);
+                self.build_model(method, features_order, data);
+            }
+            TreeMethod::Approx => {
+                let binned = BinnedFrame::from_weighted_df(
+                    x,
+                    Some(&weights),
+                    self.max_bin,
+                    &self.categorical,
+                );
+                let features_order = get_df_sorted_perm(x);
+                self.build_model(
+                    method,
+                    features_order,
+                    TrainData {
+                        binned: Some(&binned),
+                        ..data
+                    },
+                );
+            }
+            TreeMethod::Hist => {
+                let binned = BinnedFrame::from_weighted_df(
+                    x,
+                    Some(&weights),
+                    self.max_bin,
+                    &self.categorical,
+                );
+                self.build_model(
+                    method,
+                    vec![],
+                    TrainData {
+                        binned: Some(&binned),
+                        ..data
+                    },
+                );
+            }
+        }
+        if self.ccp_alpha > 0.0 {
+            self.prune_cost_complexity(self.ccp_alpha);
+        }
     }
 
     fn predict(&self, df: &DataFrame) -> DataFrame {
-        if self.nodes.is_empty() {
-            panic!("Model is not trained!");
+        let pred: Vec<V> = self
+            .apply(df)
+            .into_iter()
+            .map(|leaf| self.nodes[leaf].value)
+            .collect();
+
+        DataFrame::from_shape_vec((1, df.rows()), pred).unwrap()
+    }
+
+    fn predict_row(&self, row: ArrayView1<V>) -> V {
+        self.nodes[self.leaf_of(row)].value
+    }
+
+    fn update_leaves(&mut self, x: &DataFrame, value: &dyn Fn(&[usize]) -> Option<V>) -> bool {
+        let mut rows: Vec<Vec<usize>> = vec![vec![]; self.nodes.len()];
+        for (row, leaf) in self.apply(x).into_iter().enumerate() {
+            rows[leaf].push(row);
         }
-        let pred: Vec<V> = (0..df.rows())
-            .into_par_iter()
-            .map(|row_index| {
-                // start from root
-                let mut current_node = 0 as usize;
-                let mut value = self.nodes[current_node].value;
-                loop {
-                    match &self.nodes[current_node].info {
-                        Stem {
-                            ref feature,
-                            ref param,
-                            ref left,
-                            ref right,
-                        } => {
-                            let row_val = df[[row_index, *feature]];
-                            if row_val <= *param {
-                                current_node = *left;
-                            } else {
-                                current_node = *right;
-                            }
-                            value = self.nodes[current_node].value;
-                        }
-                        NodeInfo::Leaf => {
-                            break;
-                        }
-                    }
+        for (node, rows) in self.nodes.iter_mut().zip(rows) {
+            if rows.is_empty() {
+                continue;
+            }
+            if let Some(value) = value(&rows) {
+                // refitted values keep the monotone constraints
+                node.value = match node.bounds {
+                    Some((low, high)) => value.clamp(low, high),
+                    None => value,
+                };
+            }
+        }
+        true
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.random_state = Some(seed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data_frame::*;
+    use crate::learner::Learner;
+    use crate::tree::*;
+    use crate::utils::numeric::r2_score;
+
+    /// Two features, the label is a step function of the first one
+    fn step_data() -> (DataFrame, DataFrame) {
+        let x: Vec<V> = (0..200)
+            .flat_map(|i| vec![(i % 20) as V, (i % 7) as V])
+            .collect();
+        let y: Vec<V> = (0..200)
+            .map(|i| if i % 20 < 8 { 1.0 } else { 5.0 })
+            .collect();
+        (
+            DataFrame::from_shape_vec((200, 2), x).unwrap(),
+            DataFrame::from_shape_vec((1, 200), y).unwrap(),
+        )
+    }
+
+    #[test]
+    fn fits_step_function() {
+        let (x, y) = step_data();
+        for method in &[TreeMethod::Approx, TreeMethod::Hist, TreeMethod::Exact] {
+            let mut config = HashSet::new();
+            config.insert(DecisionTreeConfig::MaxDepth(3));
+            config.insert(DecisionTreeConfig::Method(*method));
+            let mut tree = DecisionTree::new_with_config(config);
+            tree.fit(&x, &y);
+            assert!(r2_score(&y, &tree.predict(&x)) > 0.99, "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn split_nodes_partitions_samples_stably() {
+        let x: Vec<V> = vec![
+            5., 0., 1., 1., 7., 0., 3., 1., 8., 1., 2., 0., 6., 1., 4., 0., 0., 1., 9., 0.,
+        ];
+        let x = DataFrame::from_shape_vec((10, 2), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 10), (0..10).map(|i| i as V).collect()).unwrap();
+        let weights = vec![1.0; 10];
+        let targets: Vec<(V, V)> = y.row(0).iter().map(|y| (*y, 1.0)).collect();
+        let data = TrainData {
+            df: &x,
+            labels: &y,
+            weights: &weights,
+            targets: &targets,
+            binned: None,
+            seed: 0,
+        };
+
+        let mut tree = DecisionTree::new();
+        let samples: Vec<usize> = (0..10).collect();
+        let unbounded = (V::NEG_INFINITY, V::INFINITY);
+        let (root, root_open) = tree.new_open_node(&samples, 0..10, 0, None, unbounded, data);
+        tree.nodes.push(root);
+        let feature_order = (0..2)
+            .map(|f| {
+                let mut order = samples.clone();
+                order.sort_by(|a, b| x[[*a, f]].partial_cmp(&x[[*b, f]]).unwrap());
+                order
+            })
+            .collect();
+        let mut buffers = Buffers {
+            goes_left: vec![false; 10],
+            samples,
+            feature_order,
+        };
+
+        // splits the nodes by `(feature, param)` and checks that each child's slice of every
+        // buffer holds exactly its parent's rows going its way, in the parent's order
+        let mut split_and_check = |nodes: Vec<OpenNode>, splits: &[(usize, V)]| {
+            let before = std::iter::once(buffers.samples.clone())
+                .chain(buffers.feature_order.clone())
+                .collect::<Vec<_>>();
+            let ranges: Vec<Range<usize>> = nodes.iter().map(|n| n.range.clone()).collect();
+            let infos = nodes
+                .into_iter()
+                .zip(splits)
+                .map(|(node, (feature, param))| SplitInfo {
+                    node,
+                    split: FeatureSplit {
+                        feature: *feature,
+                        param: *param,
+                        categories: None,
+                        default_left: false,
+                        impurity: 0.0,
+                    },
+                })
+                .collect();
+            let children = tree.split_nodes(infos, &mut buffers, data);
+            let mut after = std::iter::once(buffers.samples.clone())
+                .chain(buffers.feature_order.clone())
+                .collect::<Vec<_>>();
+            for (i, (feature, param)) in splits.iter().enumerate() {
+                let (left, right) = (&children[2 * i].range, &children[2 * i + 1].range);
+                assert_eq!((ranges[i].start, ranges[i].end), (left.start, right.end));
+                assert_eq!(left.end, right.start);
+                for (old, new) in before.iter().zip(&after) {
+                    let parent = &old[ranges[i].clone()];
+                    let goes_left = |row: &&usize| x[[**row, *feature]] <= *param;
+                    let expected: Vec<usize> = parent.iter().filter(goes_left).copied().collect();
+                    assert_eq!(expected, &new[left.clone()]);
+                    let expected: Vec<usize> =
+                        parent.iter().filter(|row| !goes_left(row)).copied().collect();
+                    assert_eq!(expected, &new[right.clone()]);
                 }
-                value
+            }
+            (children, after.swap_remove(0))
+        };
+
+        // the root, then both of its children at once
+        let (children, samples) = split_and_check(vec![root_open], &[(0, 4.5)]);
+        assert_eq!(vec![1, 3, 5, 7, 8, 0, 2, 4, 6, 9], samples);
+        let (children, samples) = split_and_check(children, &[(1, 0.5), (1, 0.5)]);
+        assert_eq!(4, children.len());
+        assert_eq!(vec![5, 7, 1, 3, 8, 0, 2, 9, 4, 6], samples);
+    }
+
+    #[test]
+    fn sorted_split_matches_threshold_scan() {
+        // sorted by x the labels are 1 | 2 3 (x = 2, the 3 weighs 2) | 6 | 7 | 9, the best
+        // split is x <= 2.5 with children squared errors 2.75 + 14 / 3
+        let x = DataFrame::from_shape_vec((6, 1), vec![3., 1., 2., 2., 5., 4.]).unwrap();
+        let y = DataFrame::from_shape_vec((1, 6), vec![6., 1., 2., 3., 9., 7.]).unwrap();
+        let weights: Vec<V> = vec![1., 1., 1., 2., 1., 1.];
+        let targets: Vec<(V, V)> = y.row(0).iter().zip(&weights).map(|(y, w)| (*y, *w)).collect();
+        let data = TrainData {
+            df: &x,
+            labels: &y,
+            weights: &weights,
+            targets: &targets,
+            binned: None,
+            seed: 0,
+        };
+
+        // the weighted squared errors of both children of every threshold, from scratch
+        let sse = |rows: Vec<usize>| {
+            let weight: V = rows.iter().map(|i| weights[*i]).sum();
+            let mean = rows.iter().map(|i| weights[*i] * y[[0, *i]]).sum::<V>() / weight;
+            rows.iter()
+                .map(|i| weights[*i] * (y[[0, *i]] - mean).powi(2))
+                .sum::<V>()
+        };
+        let scan: Vec<(V, V)> = [1.5, 2.5, 3.5, 4.5]
+            .iter()
+            .map(|t| {
+                let (left, right): (Vec<usize>, Vec<usize>) =
+                    (0..6).partition(|i| x[[*i, 0]] <= *t);
+                (*t, sse(left) + sse(right))
             })
             .collect();
+        let (threshold, impurity) = scan
+            .iter()
+            .copied()
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .unwrap();
+        assert_eq!(2.5, threshold);
+        assert!((impurity - (2.75 + 14.0 / 3.0)).abs() < 1e-4);
 
-        DataFrame::from_shape_vec((1, df.rows()), pred).unwrap()
+        let mut config = HashSet::new();
+        config.insert(DecisionTreeConfig::Method(TreeMethod::Exact));
+        let tree = DecisionTree::new_with_config(config);
+        let samples: Vec<usize> = (0..6).collect();
+        let unbounded = (V::NEG_INFINITY, V::INFINITY);
+        let (_, root) = tree.new_open_node(&samples, 0..6, 0, None, unbounded, data);
+        let order = vec![1, 2, 3, 0, 5, 4];
+        let split = tree.find_sorted_split(0, &order, &root.stats, data).unwrap();
+        assert_eq!(threshold, split.param);
+        assert!((impurity - split.impurity).abs() < 1e-4);
+        let gain = SplitInfo { node: root, split }.gain();
+        assert!((sse((0..6).collect()) - impurity - gain).abs() < 1e-4);
+    }
+
+    #[test]
+    fn leaf_wise_growth_splits_best_leaves() {
+        // flat on the left half, three steps on the right one: four leaves fit exactly,
+        // but only on an unbalanced tree
+        let x: Vec<V> = (0..100).map(|i| i as V).collect();
+        let y: Vec<V> = x
+            .iter()
+            .map(|x| match *x as usize {
+                0..=49 => 0.0,
+                50..=74 => 10.0,
+                75..=87 => 30.0,
+                _ => 60.0,
+            })
+            .collect();
+        let x = DataFrame::from_shape_vec((100, 1), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 100), y).unwrap();
+        for method in &[TreeMethod::Approx, TreeMethod::Hist, TreeMethod::Exact] {
+            let mut config = HashSet::new();
+            config.insert(DecisionTreeConfig::GrowPolicy(GrowPolicy::LeafWise));
+            config.insert(DecisionTreeConfig::MaxLeaves(4));
+            config.insert(DecisionTreeConfig::Method(*method));
+            let mut tree = DecisionTree::new_with_config(config);
+            tree.fit(&x, &y);
+            let leaves = tree.nodes.iter().filter(|n| matches!(n.info, NodeInfo::Leaf)).count();
+            assert_eq!(4, leaves, "{:?}", method);
+            assert!(r2_score(&y, &tree.predict(&x)) > 0.999, "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn monotone_constraints_hold_everywhere() {
+        // increasing in the first feature and decreasing in the second one, with noise
+        // that an unconstrained tree follows
+        let x: Vec<V> = (0..400)
+            .flat_map(|i| vec![(i % 40) as V, (i / 40) as V])
+            .collect();
+        let y: Vec<V> = (0..400)
+            .map(|i| (i % 40) as V - (i / 40) as V + ((i * 7919) % 11) as V)
+            .collect();
+        let x = DataFrame::from_shape_vec((400, 2), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 400), y).unwrap();
+        // every point of a grid and its neighbours in each feature
+        let grid: Vec<V> = (-1..42)
+            .flat_map(|a| (-1..12).map(move |b| (a, b)))
+            .flat_map(|(a, b)| vec![a as V, b as V])
+            .collect();
+        let grid = DataFrame::from_shape_vec((grid.len() / 2, 2), grid).unwrap();
+
+        for method in &[TreeMethod::Approx, TreeMethod::Hist, TreeMethod::Exact] {
+            let mut config = HashSet::new();
+            config.insert(DecisionTreeConfig::MonotoneConstraints(vec![1, -1]));
+            config.insert(DecisionTreeConfig::Method(*method));
+            let mut tree = DecisionTree::new_with_config(config);
+            tree.fit(&x, &y);
+            assert!(r2_score(&y, &tree.predict(&x)) > 0.5, "{:?}", method);
+
+            let pred = tree.predict(&grid);
+            let at = |a: usize, b: usize| pred[[0, a * 13 + b]];
+            for a in 0..43 {
+                for b in 0..13 {
+                    if a + 1 < 43 {
+                        assert!(at(a, b) <= at(a + 1, b), "{:?}", method);
+                    }
+                    if b + 1 < 13 {
+                        assert!(at(a, b) >= at(a, b + 1), "{:?}", method);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn interaction_constraints_hold_on_paths() {
+        let x: Vec<V> = (0..300)
+            .flat_map(|i| vec![(i % 7) as V, (i % 11) as V, (i % 13) as V])
+            .collect();
+        let y: Vec<V> = (0..300)
+            .map(|i| ((i % 7) * (i % 11)) as V + (i % 13) as V)
+            .collect();
+        let x = DataFrame::from_shape_vec((300, 3), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 300), y).unwrap();
+        let groups = vec![vec![0, 1], vec![2]];
+        for method in &[TreeMethod::Approx, TreeMethod::Hist, TreeMethod::Exact] {
+            let mut config = HashSet::new();
+            config.insert(DecisionTreeConfig::InteractionConstraints(groups.clone()));
+            config.insert(DecisionTreeConfig::Method(*method));
+            let mut tree = DecisionTree::new_with_config(config);
+            tree.fit(&x, &y);
+
+            // features split on along every path from the root
+            let mut paths = vec![(0, vec![])];
+            while let Some((index, mut path)) = paths.pop() {
+                if let Stem { feature, left, right, .. } = tree.nodes[index].info {
+                    path.push(feature);
+                    paths.push((left, path.clone()));
+                    paths.push((right, path));
+                } else {
+                    assert!(
+                        groups.iter().any(|g| path.iter().all(|f| g.contains(f))),
+                        "{:?} {:?}",
+                        method,
+                        path
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn leaf_values_match_routing() {
+        // a zero-heavy feature, equal-count split points fall inside the run of zeros
+        let x: Vec<V> = (0..300)
+            .map(|i| if i % 3 == 0 { (i % 11) as V } else { 0.0 })
+            .collect();
+        let y: Vec<V> = (0..300).map(|i| (i % 5) as V + x[i]).collect();
+        let x = DataFrame::from_shape_vec((300, 1), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 300), y).unwrap();
+
+        for method in &[TreeMethod::Approx, TreeMethod::Hist, TreeMethod::Exact] {
+            let mut config = HashSet::new();
+            config.insert(DecisionTreeConfig::MaxBin(7));
+            config.insert(DecisionTreeConfig::Method(*method));
+            let mut tree = DecisionTree::new_with_config(config);
+            tree.fit(&x, &y);
+
+            let leaves = tree.apply(&x);
+            for leaf in leaves.iter().collect::<HashSet<_>>() {
+                let routed: Vec<V> = (0..300)
+                    .filter(|i| leaves[*i] == *leaf)
+                    .map(|i| y[[0, i]])
+                    .collect();
+                let mean = routed.iter().sum::<V>() / routed.len() as V;
+                assert!((tree.nodes[*leaf].value - mean).abs() < 1e-4, "{:?}", method);
+            }
+        }
+    }
+
+    #[test]
+    fn classifies_with_class_probabilities() {
+        // class 0 below 5, class 1 in 5..12 and class 2 above
+        let x: Vec<V> = (0..150).map(|i| (i % 15) as V).collect();
+        let y: Vec<V> = x
+            .iter()
+            .map(|v| if *v < 5.0 { 0.0 } else if *v < 12.0 { 1.0 } else { 2.0 })
+            .collect();
+        let x = DataFrame::from_shape_vec((150, 1), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 150), y).unwrap();
+
+        for criterion in &[Criterion::Gini, Criterion::Entropy] {
+            for method in &[TreeMethod::Approx, TreeMethod::Hist, TreeMethod::Exact] {
+                let mut config = HashSet::new();
+                config.insert(DecisionTreeConfig::Criterion(*criterion));
+                config.insert(DecisionTreeConfig::Method(*method));
+                let mut tree = DecisionTree::new_with_config(config);
+                tree.fit(&x, &y);
+                assert_eq!(y, tree.predict(&x), "{:?} {:?}", criterion, method);
+
+                let proba = tree.predict_proba(&x);
+                assert_eq!(&[3, 150], proba.shape());
+                for row in 0..150 {
+                    assert_eq!(1.0, proba[[y[[0, row]] as usize, row]]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn balanced_class_weight_favors_rare_class() {
+        // 2 of the 10 samples with x = 1 are the only samples of class 1
+        let x: Vec<V> = (0..100).map(|i| if i < 90 { 0.0 } else { 1.0 }).collect();
+        let y: Vec<V> = (0..100).map(|i| if i >= 98 { 1.0 } else { 0.0 }).collect();
+        let x = DataFrame::from_shape_vec((100, 1), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 100), y).unwrap();
+        let rare = DataFrame::from_shape_vec((1, 1), vec![1.0]).unwrap();
+
+        let mut config = HashSet::new();
+        config.insert(DecisionTreeConfig::Criterion(Criterion::Gini));
+        config.insert(DecisionTreeConfig::MaxDepth(1));
+        let mut tree = DecisionTree::new_with_config(config.clone());
+        tree.fit(&x, &y);
+        assert_eq!(0.0, tree.predict(&rare)[[0, 0]]);
+        assert!((tree.predict_proba(&rare)[[1, 0]] - 0.2).abs() < 1e-6);
+
+        config.insert(DecisionTreeConfig::ClassWeight(ClassWeight::Balanced));
+        let mut tree = DecisionTree::new_with_config(config);
+        tree.fit(&x, &y);
+        assert_eq!(1.0, tree.predict(&rare)[[0, 0]]);
+    }
+
+    #[test]
+    fn regression_criteria_fit_step_function() {
+        let (x, y) = step_data();
+        for criterion in &[Criterion::FriedmanMse, Criterion::Mae, Criterion::Poisson] {
+            for method in &[TreeMethod::Approx, TreeMethod::Hist, TreeMethod::Exact] {
+                let mut config = HashSet::new();
+                config.insert(DecisionTreeConfig::MaxDepth(3));
+                config.insert(DecisionTreeConfig::Method(*method));
+                config.insert(DecisionTreeConfig::Criterion(*criterion));
+                let mut tree = DecisionTree::new_with_config(config);
+                tree.fit(&x, &y);
+                assert!(r2_score(&y, &tree.predict(&x)) > 0.99, "{:?} {:?}", criterion, method);
+            }
+        }
+    }
+
+    #[test]
+    fn absolute_error_leaves_predict_medians() {
+        // the outlier pulls the mean of the labels but not their median
+        let x = DataFrame::from_shape_vec((5, 1), vec![0., 1., 2., 3., 4.]).unwrap();
+        let y = DataFrame::from_shape_vec((1, 5), vec![1., 2., 3., 2., 90.]).unwrap();
+        let mut config = HashSet::new();
+        config.insert(DecisionTreeConfig::MaxDepth(0));
+        config.insert(DecisionTreeConfig::Criterion(Criterion::Mae));
+        let mut tree = DecisionTree::new_with_config(config);
+        tree.fit(&x, &y);
+        assert_eq!(2.0, tree.predict(&x)[[0, 4]]);
+        assert_eq!(90.0, tree.nodes[0].variance);
+    }
+
+    #[test]
+    fn learns_default_direction_of_missing_values() {
+        // missing values have the labels of the small values
+        let x: Vec<V> = (0..120)
+            .map(|i| if i % 4 == 0 { V::NAN } else { (i % 10) as V })
+            .collect();
+        let y: Vec<V> = x
+            .iter()
+            .map(|v| if v.is_nan() || *v < 5.0 { 1.0 } else { 5.0 })
+            .collect();
+        let x = DataFrame::from_shape_vec((120, 1), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 120), y).unwrap();
+
+        for criterion in &[Criterion::Mse, Criterion::Mae] {
+            for method in &[TreeMethod::Approx, TreeMethod::Hist, TreeMethod::Exact] {
+                let mut config = HashSet::new();
+                config.insert(DecisionTreeConfig::MaxDepth(1));
+                config.insert(DecisionTreeConfig::Method(*method));
+                config.insert(DecisionTreeConfig::Criterion(*criterion));
+                let mut tree = DecisionTree::new_with_config(config);
+                tree.fit(&x, &y);
+                assert!(
+                    matches!(tree.nodes[0].info, Stem { default_left: true, .. }),
+                    "{:?} {:?}",
+                    criterion,
+                    method
+                );
+                assert_eq!(y, tree.predict(&x), "{:?} {:?}", criterion, method);
+            }
+        }
+    }
+
+    #[test]
+    fn splits_categories_by_subset() {
+        // categories 1, 4 and 7 have high labels, no threshold separates them
+        let x: Vec<V> = (0..200)
+            .map(|i| if i % 13 == 0 { V::NAN } else { (i % 10) as V })
+            .collect();
+        let y: Vec<V> = x
+            .iter()
+            .map(|v| if [1.0, 4.0, 7.0].contains(v) { 1.0 } else { 0.0 })
+            .collect();
+        let x = DataFrame::from_shape_vec((200, 1), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 200), y).unwrap();
+
+        for criterion in &[Criterion::Mse, Criterion::Mae, Criterion::Gini] {
+            for method in &[TreeMethod::Approx, TreeMethod::Hist, TreeMethod::Exact] {
+                let mut config = HashSet::new();
+                config.insert(DecisionTreeConfig::MaxDepth(1));
+                config.insert(DecisionTreeConfig::Method(*method));
+                config.insert(DecisionTreeConfig::Criterion(*criterion));
+                config.insert(DecisionTreeConfig::Categorical(vec![0]));
+                let mut tree = DecisionTree::new_with_config(config);
+                tree.fit(&x, &y);
+                assert_eq!(y, tree.predict(&x), "{:?} {:?}", criterion, method);
+            }
+        }
+    }
+
+    /// The root's left categories of a stump grown on `categories` categories whose label
+    /// is `label(category)`
+    fn root_categories(
+        categories: usize,
+        label: fn(usize) -> V,
+        config: Vec<DecisionTreeConfig>,
+    ) -> Vec<usize> {
+        let x: Vec<V> = (0..300).map(|i| (i % categories) as V).collect();
+        let y: Vec<V> = (0..300).map(|i| label(i % categories)).collect();
+        let x = DataFrame::from_shape_vec((300, 1), x).unwrap();
+        let y = DataFrame::from_shape_vec((1, 300), y).unwrap();
+        let mut config: HashSet<DecisionTreeConfig> = config.into_iter().collect();
+        config.insert(DecisionTreeConfig::MaxDepth(1));
+        config.insert(DecisionTreeConfig::Categorical(vec![0]));
+        let mut tree = DecisionTree::new_with_config(config);
+        tree.fit(&x, &y);
+        match &tree.nodes[0].info {
+            NodeInfo::CategoryStem { categories: set, .. } => {
+                (0..categories).filter(|c| set.contains(*c)).collect()
+            }
+            _ => panic!("the root does not split by categories"),
+        }
+    }
+
+    #[test]
+    fn few_categories_split_by_sorted_subsets() {
+        // three classes over six categories, sorted subsets group the categories of a class
+        let gini = vec![DecisionTreeConfig::Criterion(Criterion::Gini)];
+        let left = root_categories(6, |c| (c % 3) as V, gini);
+        assert!(left.len() == 2 || left.len() == 4, "{:?}", left);
+        assert!(left.iter().all(|c| left.contains(&((c + 3) % 6))), "{:?}", left);
+    }
+
+    #[test]
+    fn many_categories_split_one_vs_rest() {
+        // a binary target over more categories than are sorted
+        let label = |c: usize| if c.is_multiple_of(3) { 1.0 } else { 0.0 };
+        let config = vec![DecisionTreeConfig::MaxCategoriesSorted(5)];
+        assert_eq!(1, root_categories(12, label, config).len());
+        // sorted, the 4 categories of label 1 split from the 8 others
+        let sorted = root_categories(12, label, vec![]);
+        assert!(sorted.len() == 4 || sorted.len() == 8, "{:?}", sorted);
+    }
+
+    #[test]
+    fn weights_scale_leaf_values() {
+        let x = DataFrame::from_shape_vec((4, 1), vec![0., 1., 2., 3.]).unwrap();
+        let y = DataFrame::from_shape_vec((1, 4), vec![1., 2., 100., 4.]).unwrap();
+        // the outlier weighs nothing, the last sample counts three times
+        let w = DataFrame::from_shape_vec((1, 4), vec![1., 1., 0., 3.]).unwrap();
+        for method in &[TreeMethod::Approx, TreeMethod::Hist, TreeMethod::Exact] {
+            let mut config = HashSet::new();
+            config.insert(DecisionTreeConfig::MaxDepth(0));
+            config.insert(DecisionTreeConfig::Method(*method));
+            let mut tree = DecisionTree::new_with_config(config);
+            tree.fit_weighted(&x, &y, &w);
+            assert!((tree.nodes[0].value - 3.0).abs() < 1e-6, "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn models_saved_before_new_options_still_load() {
+        // a stump as saved before trees had methods, criteria and constraints
+        let stump = r#"{
+            "nodes": [
+                {"value": 1.5, "index": 0, "depth": 0, "variance": 1.0,
+                 "info": {"Stem": {"feature": 0, "param": 0.5, "left": 1, "right": 2}}},
+                {"value": 1.0, "index": 1, "depth": 1, "variance": 0.0, "info": "Leaf"},
+                {"value": 2.0, "index": 2, "depth": 1, "variance": 0.0, "info": "Leaf"}
+            ],
+            "max_depth": 1, "max_features": 1, "min_samples_split": 3,
+            "min_samples_leaf": 1, "max_bin": 255
+        }"#;
+        let tree: DecisionTree = serde_json::from_str(stump).unwrap();
+        let new = DecisionTree::new();
+        assert_eq!(new.method, tree.method);
+        assert_eq!(new.criterion, tree.criterion);
+        assert_eq!(new.class_weight, tree.class_weight);
+        assert_eq!(new.grow_policy, tree.grow_policy);
+        assert_eq!(new.max_leaves, tree.max_leaves);
+        assert_eq!((0, 0.0), (tree.n_classes, tree.ccp_alpha));
+        assert!(tree.categorical.is_empty() && tree.monotone_constraints.is_empty());
+        assert!(tree.interaction_constraints.is_empty() && tree.random_state.is_none());
+
+        let boost = format!(
+            r#"{{"weak_learner": {0}, "learners": [{0}], "learning_rates": [0.5],
+                "max_iterations": 1, "sub_sample": 1.0, "init_value": 1.0}}"#,
+            stump
+        );
+        let boost: crate::boosting::GradientBoosting<DecisionTree> = serde_json::from_str(&boost).unwrap();
+        let x = DataFrame::from_shape_vec((2, 1), vec![0.0, 1.0]).unwrap();
+        assert_eq!(vec![1.5, 2.0], boost.predict(&x).into_raw_vec());
     }
 }
@@ -0,0 +1,213 @@
+use crate::data_frame::*;
+use crate::utils::quantile::QuantileSketch;
+use rayon::prelude::*;
+
+/// Size of quantile summaries relative to the number of bins
+const SKETCH_SIZE_FACTOR: usize = 8;
+/// Number of rows sketched by one task before sketches are merged
+const SKETCH_CHUNK_ROWS: usize = 1 << 16;
+
+/// Bin codes of a single feature column, stored in the narrowest integer type
+/// that can hold every bin of the feature
+#[derive(Debug, Clone)]
+pub enum BinCodes {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+}
+
+impl BinCodes {
+    #[inline]
+    pub fn get(&self, row: usize) -> usize {
+        match self {
+            BinCodes::U8(codes) => codes[row] as usize,
+            BinCodes::U16(codes) => codes[row] as usize,
+        }
+    }
+}
+
+/// Maps the values of one feature to bins.
+/// Bin `i` holds the values in `(upper_bounds[i - 1], upper_bounds[i]]`,
+/// the last upper bound is always infinity. Missing values get the extra bin `bins()`.
+#[derive(Debug, Clone)]
+pub struct BinMapper {
+    pub upper_bounds: Vec<V>,
+}
+
+impl BinMapper {
+    /// Bins holding about the same weight of values according to a quantile sketch
+    pub fn from_sketch(sketch: &QuantileSketch, max_bin: usize) -> Self {
+        Self {
+            upper_bounds: sketch.cut_points(max_bin),
+        }
+    }
+
+    /// One bin per category code `0..n_categories`
+    pub fn categorical(n_categories: usize) -> Self {
+        let mut upper_bounds: Vec<V> = (1..n_categories).map(|c| c as V - 0.5).collect();
+        upper_bounds.push(V::INFINITY);
+        Self { upper_bounds }
+    }
+
+    /// Builds bins holding about the same number of values of `col`
+    pub fn from_values(col: &[V], max_bin: usize) -> Self {
+        let mut sketch = QuantileSketch::new(max_bin * SKETCH_SIZE_FACTOR);
+        for val in col {
+            sketch.push(*val, 1.0);
+        }
+        Self::from_sketch(&sketch, max_bin)
+    }
+
+    pub fn bins(&self) -> usize {
+        self.upper_bounds.len()
+    }
+
+    /// The bin holding missing values, after every bin of present values
+    pub fn missing_bin(&self) -> usize {
+        self.bins()
+    }
+
+    /// The bin the value falls into
+    #[inline]
+    pub fn bin_of(&self, val: V) -> usize {
+        if val.is_nan() {
+            return self.missing_bin();
+        }
+        let bin = self.upper_bounds.partition_point(|bound| *bound < val);
+        bin.min(self.bins() - 1)
+    }
+
+    /// The split threshold that sends bins `0..=bin` left
+    pub fn threshold(&self, bin: usize) -> V {
+        self.upper_bounds[bin]
+    }
+}
+
+/// A data frame whose columns are quantized once into bin codes,
+/// used by histogram based split finding
+#[derive(Debug, Clone)]
+pub struct BinnedFrame {
+    /// Bin boundaries of each feature
+    pub mappers: Vec<BinMapper>,
+    /// Bin codes of each feature, one entry per sample
+    pub codes: Vec<BinCodes>,
+    rows: usize,
+}
+
+impl BinnedFrame {
+    pub fn from_df(df: &DataFrame, max_bin: usize) -> Self {
+        Self::from_weighted_df(df, None, max_bin, &[])
+    }
+
+    /// Bin boundaries of each feature come from a weighted quantile sketch of the column,
+    /// so bins hold about the same weight of samples. Sketches are built in parallel over
+    /// chunks of rows and merged, no column needs to be sorted.
+    /// `max_bin` is capped to 65535 so every code, including the missing bin, fits in `u16`.
+    /// `categorical` features get one bin per category code instead.
+    pub fn from_weighted_df(
+        df: &DataFrame,
+        weights: Option<&[V]>,
+        max_bin: usize,
+        categorical: &[usize],
+    ) -> Self {
+        let max_bin = max_bin.clamp(2, u16::MAX as usize);
+        let chunks = df.rows().div_ceil(SKETCH_CHUNK_ROWS);
+        let mappers: Vec<BinMapper> = (0..df.cols())
+            .into_par_iter()
+            .map(|feature| {
+                if categorical.contains(&feature) {
+                    let max_category = df
+                        .column(feature)
+                        .iter()
+                        .filter(|v| !v.is_nan())
+                        .fold(0.0, |max: V, v| max.max(*v));
+                    if max_category >= u16::MAX as V {
+                        panic!("Categorical feature {} has more than {} categories", feature, u16::MAX);
+                    }
+                    return BinMapper::categorical(max_category as usize + 1);
+                }
+                let sketch = (0..chunks)
+                    .into_par_iter()
+                    .map(|chunk| {
+                        let mut sketch = QuantileSketch::new(max_bin * SKETCH_SIZE_FACTOR);
+                        let end = df.rows().min((chunk + 1) * SKETCH_CHUNK_ROWS);
+                        for row in chunk * SKETCH_CHUNK_ROWS..end {
+                            sketch.push(df[[row, feature]], weights.map_or(1.0, |w| w[row]));
+                        }
+                        sketch
+                    })
+                    .reduce(
+                        || QuantileSketch::new(max_bin * SKETCH_SIZE_FACTOR),
+                        |mut a, b| {
+                            a.merge(&b);
+                            a
+                        },
+                    );
+                BinMapper::from_sketch(&sketch, max_bin)
+            })
+            .collect();
+        Self::with_mappers(df, mappers)
+    }
+
+    /// Quantize `df` with existing bin boundaries
+    pub fn with_mappers(df: &DataFrame, mappers: Vec<BinMapper>) -> Self {
+        let codes = mappers
+            .par_iter()
+            .enumerate()
+            .map(|(feature, mapper)| {
+                let col = df.column(feature);
+                if mapper.missing_bin() <= u8::MAX as usize {
+                    BinCodes::U8(col.iter().map(|v| mapper.bin_of(*v) as u8).collect())
+                } else {
+                    BinCodes::U16(col.iter().map(|v| mapper.bin_of(*v) as u16).collect())
+                }
+            })
+            .collect();
+
+        Self {
+            mappers,
+            codes,
+            rows: df.rows(),
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.mappers.len()
+    }
+
+    #[inline]
+    pub fn code(&self, row: usize, feature: usize) -> usize {
+        self.codes[feature].get(row)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::binning::*;
+    use ndarray::*;
+
+    #[test]
+    fn distinct_values_get_own_bins() {
+        let mapper = BinMapper::from_values(&[3.0, 1.0, f32::NAN, 1.0, 2.0, 3.0], 255);
+        assert_eq!(vec![1.5, 2.5, f32::INFINITY], mapper.upper_bounds);
+        assert_eq!(0, mapper.bin_of(1.0));
+        assert_eq!(1, mapper.bin_of(2.0));
+        assert_eq!(2, mapper.bin_of(3.0));
+        assert_eq!(3, mapper.bin_of(f32::NAN));
+    }
+
+    #[test]
+    fn codes_respect_max_bin() {
+        let df = Array2::from_shape_vec((1000, 1), (0..1000).map(|v| v as f32).collect()).unwrap();
+        let binned = BinnedFrame::from_df(&df, 10);
+        assert_eq!(10, binned.mappers[0].bins());
+        assert_eq!(0, binned.code(0, 0));
+        assert_eq!(9, binned.code(999, 0));
+        for row in 1..1000 {
+            assert!(binned.code(row - 1, 0) <= binned.code(row, 0));
+        }
+    }
+}
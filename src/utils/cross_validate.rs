@@ -1,12 +1,17 @@
 use crate::data_frame::*;
 use crate::learner::Learner;
+use crate::ranking::group_offsets;
+use crate::utils::random::*;
 use log::*;
+use ndarray::Axis;
 use rand::prelude::*;
 
 use std::time;
 
 pub struct KFold {
     pub splits: usize,
+    /// Seed of the fold shuffling and of the models, a random one is drawn if `None`
+    pub random_state: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -19,7 +24,18 @@ pub struct CrossValidateScore {
 
 impl KFold {
     pub fn new(k: usize) -> Self {
-        Self { splits: k }
+        Self {
+            splits: k,
+            random_state: None,
+        }
+    }
+
+    /// `k` folds shuffled with `seed`
+    pub fn with_seed(k: usize, seed: u64) -> Self {
+        Self {
+            splits: k,
+            random_state: Some(seed),
+        }
     }
 
     pub fn cross_validate<L, M>(
@@ -33,12 +49,36 @@ impl KFold {
         L: Learner + Clone,
         M: Fn(&DataFrame, &DataFrame) -> V,
     {
-        let samples = x.rows();
-        let fold_size = samples / self.splits;
-        let mut rng = rand::thread_rng();
-        // Shuffle sample order
-        let mut sample_orders: Vec<usize> = (0..samples).into_iter().collect();
-        sample_orders.shuffle(&mut rng);
+        if learner.needs_groups() {
+            panic!("The learner ranks query groups, cross validate it with cross_validate_grouped");
+        }
+        let groups = vec![1; x.rows()];
+        self.cross_validate_grouped(learner, x, y, &groups, |y, pred, _| metric(y, pred))
+    }
+
+    /// Cross validation on rows coming in consecutive query groups of the sizes `groups`:
+    /// whole groups are shuffled and split into folds, the model fits with
+    /// `Learner::fit_grouped` and `metric` gets the groups of the scored rows
+    pub fn cross_validate_grouped<L, M>(
+        &self,
+        learner: L,
+        x: &DataFrame,
+        y: &DataFrame,
+        groups: &[usize],
+        metric: M,
+    ) -> CrossValidateScore
+    where
+        L: Learner + Clone,
+        M: Fn(&DataFrame, &DataFrame, &[usize]) -> V,
+    {
+        let offsets = group_offsets(groups);
+        assert_eq!(x.rows(), *offsets.last().unwrap(), "groups must cover every row");
+        let fold_size = groups.len() / self.splits;
+        let seed = seed_or_random(self.random_state);
+        let mut rng = seeded_rng(derive_seed(seed, 0));
+        // Shuffle group order
+        let mut group_orders: Vec<usize> = (0..groups.len()).collect();
+        group_orders.shuffle(&mut rng);
         let mut results = CrossValidateScore {
             train_time: vec![],
             predict_time: vec![],
@@ -48,49 +88,49 @@ impl KFold {
 
         for i in 0..self.splits {
             let test_range = (fold_size * i, fold_size * (i + 1));
-            let mut train_df = Vec::new();
-            let mut train_labels = vec![];
-            let mut test_df = Vec::new();
-            let mut test_labels = vec![];
-            for k in 0..samples {
-                let mut row = vec![];
-                let k_sample = sample_orders[k];
-                for j in 0..x.cols() {
-                    row.push(x[[k_sample, j]]);
-                }
+            let mut train_rows = vec![];
+            let mut train_groups = vec![];
+            let mut test_rows = vec![];
+            let mut test_groups = vec![];
+            for (k, &k_group) in group_orders.iter().enumerate() {
+                let rows = offsets[k_group]..offsets[k_group + 1];
                 if k < test_range.1 && k >= test_range.0 {
                     // Validation set
-                    test_df.extend(row);
-                    test_labels.push(y[[0, k_sample]])
+                    test_rows.extend(rows);
+                    test_groups.push(groups[k_group]);
                 } else {
                     // Training set
-                    train_df.extend(row);
-                    train_labels.push(y[[0, k_sample]])
+                    train_rows.extend(rows);
+                    train_groups.push(groups[k_group]);
                 }
             }
 
-            let train_df =
-                DataFrame::from_shape_vec((samples - fold_size, x.cols()), train_df).unwrap();
-            let test_df = DataFrame::from_shape_vec((fold_size, x.cols()), test_df).unwrap();
-            let train_labels =
-                DataFrame::from_shape_vec((1, samples - fold_size), train_labels).unwrap();
-            let test_labels = DataFrame::from_shape_vec((1, fold_size), test_labels).unwrap();
+            let train_df = x.select(Axis(0), &train_rows);
+            let test_df = x.select(Axis(0), &test_rows);
+            let train_labels = y.select(Axis(1), &train_rows);
+            let test_labels = y.select(Axis(1), &test_rows);
 
             let start = time::SystemTime::now();
             let mut model = learner.clone();
-            model.fit(&train_df, &train_labels);
+            if self.random_state.is_some() {
+                model.set_seed(derive_seed(seed, i as u64 + 1));
+            }
+            let train_w = DataFrame::ones((1, train_rows.len()));
+            model.fit_grouped(&train_df, &train_labels, &train_w, &train_groups);
             results
                 .train_time
                 .push(start.elapsed().unwrap().as_millis());
 
             let train_pred = model.predict(&train_df);
-            results.train_score.push(metric(&train_labels, &train_pred));
+            results
+                .train_score
+                .push(metric(&train_labels, &train_pred, &train_groups));
 
             let start = time::SystemTime::now();
             let test_pred = model.predict(&test_df);
             results
                 .validation_score
-                .push(metric(&test_labels, &test_pred));
+                .push(metric(&test_labels, &test_pred, &test_groups));
             results
                 .predict_time
                 .push(start.elapsed().unwrap().as_millis());
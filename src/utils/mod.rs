@@ -1,5 +1,8 @@
+pub mod binning;
 pub mod cross_validate;
 pub mod numeric;
+pub mod quantile;
+pub mod random;
 pub mod sort_array;
 
 use rayon::ThreadPoolBuilder;
@@ -10,7 +10,7 @@ pub fn mse_score(a: &DataFrame, b: &DataFrame) -> V {
     for i in 0..a.cols() {
         result += (a[[0, i]] - b[[0, i]]).powi(2);
     }
-    (result / a.cols() as V)
+    result / a.cols() as V
 }
 
 /// a and b should both be of size (1, sample_len)
@@ -20,7 +20,7 @@ pub fn slice_mse_score(a: &[V], b: &[V]) -> V {
     for i in 0..a.len() {
         result += (a[i] - b[i]).powi(2);
     }
-    (result / a.len() as V)
+    result / a.len() as V
 }
 
 /// Calculate the square sum of all elements in a minus the mean of a
@@ -57,6 +57,32 @@ pub fn r2_score(true_y: &DataFrame, pred_y: &DataFrame) -> V {
     1.0 - (square_error / mean_diff)
 }
 
+/// R2 score with samples weighted by `w`, all of size (1, sample_len)
+pub fn weighted_r2_score(true_y: &DataFrame, pred_y: &DataFrame, w: &DataFrame) -> V {
+    assert_eq!(true_y.shape(), pred_y.shape());
+    assert_eq!(true_y.shape(), w.shape());
+    let weight: V = w.sum();
+    let true_mean = (true_y * w).sum() / weight;
+    let mut square_error: V = 0.0;
+    let mut mean_diff: V = 0.0;
+    for i in 0..true_y.cols() {
+        square_error += w[[0, i]] * (true_y[[0, i]] - pred_y[[0, i]]).powi(2);
+        mean_diff += w[[0, i]] * (true_y[[0, i]] - true_mean).powi(2);
+    }
+    1.0 - (square_error / mean_diff)
+}
+
+/// A split threshold between two sorted distinct values `a < b`
+/// such that `a <= threshold < b`
+pub fn split_midpoint(a: V, b: V) -> V {
+    let mid = a + (b - a) / 2.0;
+    if mid < b {
+        mid
+    } else {
+        a
+    }
+}
+
 pub fn float_cmp<F: Float + PartialOrd>(a: F, b: F) -> Ordering {
     if a < b {
         Ordering::Less
@@ -69,6 +95,7 @@ pub fn float_cmp<F: Float + PartialOrd>(a: F, b: F) -> Ordering {
 
 // Test will only pass if V is f64
 #[cfg(test)]
+#[allow(clippy::excessive_precision)]
 mod test {
     use crate::utils::numeric::{mse_score, r2_score};
     use ndarray::*;
@@ -77,7 +104,7 @@ mod test {
     fn mse_test() {
         let a = array![[3.1, -0.5, 2.66, 7.6]];
         let b = array![[2.5, 0.0, 2.4, 8.4]];
-        assert_eq!(0.32940000000000036, mse_score(&a, &b));
+        assert!((0.3294 - mse_score(&a, &b)).abs() < 1e-5);
     }
 
     #[test]
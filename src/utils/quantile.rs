@@ -0,0 +1,295 @@
+use crate::data_frame::*;
+use crate::utils::numeric::split_midpoint;
+use std::cmp::Ordering;
+
+/// An entry of a weighted quantile summary
+#[derive(Debug, Clone, Copy)]
+struct SketchEntry {
+    value: V,
+    /// Lower bound of the total weight of values less than `value`
+    rmin: f64,
+    /// Upper bound of the total weight of values less than or equal to `value`
+    rmax: f64,
+    /// The weight of `value` itself
+    weight: f64,
+}
+
+impl SketchEntry {
+    /// Lower bound of the total weight of values less than or equal to `value`
+    fn rmin_next(&self) -> f64 {
+        self.rmin + self.weight
+    }
+
+    /// Upper bound of the total weight of values less than `value`
+    fn rmax_prev(&self) -> f64 {
+        self.rmax - self.weight
+    }
+
+    /// The estimated rank of `value`
+    fn mid_rank(&self) -> f64 {
+        (self.rmin + self.rmax) / 2.0
+    }
+}
+
+/// Weighted quantile sketch in the style of XGBoost.
+///
+/// Values are buffered and summarized into at most `max_size` entries. Summaries are
+/// kept in levels like a binary counter so that each value only goes through a
+/// logarithmic number of prunes, the rank error is about
+/// `total_weight * log2(n / max_size) / max_size`.
+/// Sketches built over different chunks of data can be merged.
+#[derive(Debug, Clone)]
+pub struct QuantileSketch {
+    max_size: usize,
+    buffer: Vec<(V, f64)>,
+    /// `levels[i]` summarizes about `2^i` buffers, empty when unused
+    levels: Vec<Vec<SketchEntry>>,
+}
+
+impl QuantileSketch {
+    pub fn new(max_size: usize) -> Self {
+        let max_size = max_size.max(2);
+        Self {
+            max_size,
+            buffer: Vec::with_capacity(max_size),
+            levels: vec![],
+        }
+    }
+
+    /// Adds a value with its weight, NaN values and non-positive weights are ignored
+    pub fn push(&mut self, value: V, weight: V) {
+        if value.is_nan() || weight <= 0.0 {
+            return;
+        }
+        self.buffer.push((value, weight as f64));
+        if self.buffer.len() >= self.max_size {
+            let summary = Self::summarize(&mut self.buffer);
+            self.buffer.clear();
+            self.insert(summary);
+        }
+    }
+
+    /// Merges another sketch, e.g. one built on a different chunk of the data
+    pub fn merge(&mut self, other: &QuantileSketch) {
+        let summary = other.summary();
+        if !summary.is_empty() {
+            self.insert(summary);
+        }
+    }
+
+    pub fn total_weight(&self) -> f64 {
+        self.summary().last().map_or(0.0, |e| e.rmax)
+    }
+
+    /// The value whose estimated weighted rank is closest to `rank`
+    pub fn query(&self, rank: f64) -> Option<V> {
+        let summary = self.summary();
+        summary
+            .get(Self::entry_at(&summary, rank))
+            .map(|entry| entry.value)
+    }
+
+    /// Upper bounds of at most `max_bin` bins holding about the same weight, the last
+    /// bound is infinity. Bounds are midpoints between two summarized values, and every
+    /// summarized value gets its own bin when there are at most `max_bin` of them.
+    pub fn cut_points(&self, max_bin: usize) -> Vec<V> {
+        let summary = self.summary();
+        let mut bounds = Vec::with_capacity(max_bin.min(summary.len()));
+        if summary.len() <= max_bin {
+            for pair in summary.windows(2) {
+                bounds.push(split_midpoint(pair[0].value, pair[1].value));
+            }
+        } else {
+            let total = summary.last().map_or(0.0, |e| e.rmax);
+            let mut last_cut = None;
+            for k in 1..max_bin {
+                let i = Self::entry_at(&summary, total * k as f64 / max_bin as f64);
+                if i + 1 < summary.len() && last_cut.is_none_or(|last| i > last) {
+                    bounds.push(split_midpoint(summary[i].value, summary[i + 1].value));
+                    last_cut = Some(i);
+                }
+            }
+        }
+        bounds.push(V::INFINITY);
+        bounds
+    }
+
+    /// Exact summary of the buffered values
+    fn summarize(buffer: &mut [(V, f64)]) -> Vec<SketchEntry> {
+        buffer.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        let mut summary: Vec<SketchEntry> = Vec::with_capacity(buffer.len());
+        let mut seen = 0.0;
+        for (value, weight) in buffer.iter() {
+            match summary.last_mut() {
+                Some(last) if last.value == *value => {
+                    last.rmax += weight;
+                    last.weight += weight;
+                }
+                _ => summary.push(SketchEntry {
+                    value: *value,
+                    rmin: seen,
+                    rmax: seen + weight,
+                    weight: *weight,
+                }),
+            }
+            seen += weight;
+        }
+        summary
+    }
+
+    /// Carries a summary up the levels, merging it with summaries of the same level
+    fn insert(&mut self, mut summary: Vec<SketchEntry>) {
+        for level in self.levels.iter_mut() {
+            if level.is_empty() {
+                *level = summary;
+                return;
+            }
+            summary = Self::prune(Self::combine(level, &summary), self.max_size);
+            level.clear();
+        }
+        self.levels.push(summary);
+    }
+
+    /// One summary of everything pushed so far
+    fn summary(&self) -> Vec<SketchEntry> {
+        let mut buffer = self.buffer.clone();
+        let mut summary = Self::summarize(&mut buffer);
+        for level in &self.levels {
+            if !level.is_empty() {
+                summary = Self::combine(level, &summary);
+            }
+        }
+        Self::prune(summary, self.max_size)
+    }
+
+    /// Merges two summaries, ranks of an entry are shifted by the ranks
+    /// the other summary gives to its value
+    fn combine(a: &[SketchEntry], b: &[SketchEntry]) -> Vec<SketchEntry> {
+        let mut result = Vec::with_capacity(a.len() + b.len());
+        let (mut i, mut j) = (0, 0);
+        let (mut a_prev_rmin, mut b_prev_rmin) = (0.0, 0.0);
+        while i < a.len() && j < b.len() {
+            if a[i].value == b[j].value {
+                result.push(SketchEntry {
+                    value: a[i].value,
+                    rmin: a[i].rmin + b[j].rmin,
+                    rmax: a[i].rmax + b[j].rmax,
+                    weight: a[i].weight + b[j].weight,
+                });
+                a_prev_rmin = a[i].rmin_next();
+                b_prev_rmin = b[j].rmin_next();
+                i += 1;
+                j += 1;
+            } else if a[i].value < b[j].value {
+                result.push(SketchEntry {
+                    rmin: a[i].rmin + b_prev_rmin,
+                    rmax: a[i].rmax + b[j].rmax_prev(),
+                    ..a[i]
+                });
+                a_prev_rmin = a[i].rmin_next();
+                i += 1;
+            } else {
+                result.push(SketchEntry {
+                    rmin: b[j].rmin + a_prev_rmin,
+                    rmax: b[j].rmax + a[i].rmax_prev(),
+                    ..b[j]
+                });
+                b_prev_rmin = b[j].rmin_next();
+                j += 1;
+            }
+        }
+        let a_total = a.last().map_or(0.0, |e| e.rmax);
+        let b_total = b.last().map_or(0.0, |e| e.rmax);
+        for entry in &a[i..] {
+            result.push(SketchEntry {
+                rmin: entry.rmin + b_prev_rmin,
+                rmax: entry.rmax + b_total,
+                ..*entry
+            });
+        }
+        for entry in &b[j..] {
+            result.push(SketchEntry {
+                rmin: entry.rmin + a_prev_rmin,
+                rmax: entry.rmax + a_total,
+                ..*entry
+            });
+        }
+        result
+    }
+
+    /// Keeps the first and last entries and the entries closest to evenly spaced ranks
+    fn prune(summary: Vec<SketchEntry>, max_size: usize) -> Vec<SketchEntry> {
+        if summary.len() <= max_size {
+            return summary;
+        }
+        let total = summary[summary.len() - 1].rmax;
+        let mut pruned = Vec::with_capacity(max_size);
+        pruned.push(summary[0]);
+        let mut last = 0;
+        for k in 1..max_size - 1 {
+            let rank = total * k as f64 / (max_size - 1) as f64;
+            let i = Self::entry_at(&summary, rank);
+            if i > last && i < summary.len() - 1 {
+                pruned.push(summary[i]);
+                last = i;
+            }
+        }
+        pruned.push(summary[summary.len() - 1]);
+        pruned
+    }
+
+    /// Index of the entry whose estimated rank is closest to `rank`
+    fn entry_at(summary: &[SketchEntry], rank: f64) -> usize {
+        let i = summary.partition_point(|e| e.mid_rank() < rank);
+        if i == 0 {
+            0
+        } else if i == summary.len() || rank - summary[i - 1].mid_rank() < summary[i].mid_rank() - rank {
+            i - 1
+        } else {
+            i
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::quantile::*;
+
+    #[test]
+    fn approximates_quantiles() {
+        let mut sketch = QuantileSketch::new(64);
+        for i in 0..100_000 {
+            // shuffle the pushing order a little
+            sketch.push(((i * 7919) % 100_000) as V, 1.0);
+        }
+        assert_eq!(100_000.0, sketch.total_weight());
+        for q in &[0.1, 0.5, 0.9] {
+            let value = sketch.query(100_000.0 * q).unwrap();
+            assert!((value / 100_000.0 - *q as V).abs() < 0.05, "{} {}", q, value);
+        }
+    }
+
+    #[test]
+    fn merges_weighted_chunks() {
+        let mut low = QuantileSketch::new(64);
+        let mut high = QuantileSketch::new(64);
+        for i in 0..1000 {
+            low.push(i as V, 1.0);
+            // the upper half weighs three times more
+            high.push((1000 + i) as V, 3.0);
+        }
+        low.merge(&high);
+        assert_eq!(4000.0, low.total_weight());
+        let median = low.query(2000.0).unwrap();
+        assert!((median - 1333.0).abs() < 60.0, "{}", median);
+    }
+
+    #[test]
+    fn few_values_get_own_bins() {
+        let mut sketch = QuantileSketch::new(16);
+        for i in 0..1000 {
+            sketch.push((i % 3) as V, 1.0);
+        }
+        assert_eq!(vec![0.5, 1.5, V::INFINITY], sketch.cut_points(255));
+    }
+}
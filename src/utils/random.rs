@@ -0,0 +1,22 @@
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+/// The seed of the `stream`th random stream derived from `seed`, mixed with SplitMix64
+/// so that nearby seeds and streams give unrelated generators
+pub fn derive_seed(seed: u64, stream: u64) -> u64 {
+    let mut z = seed
+        .wrapping_add(stream.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// `seed`, or a random one drawn from the thread generator if `None`
+pub fn seed_or_random(seed: Option<u64>) -> u64 {
+    seed.unwrap_or_else(|| rand::thread_rng().gen())
+}
+
+/// A generator giving the same numbers for the same `seed`
+pub fn seeded_rng(seed: u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
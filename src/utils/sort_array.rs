@@ -7,7 +7,7 @@ use rayon::prelude::*;
 use std::time;
 
 use log::*;
-use std::cmp::Ordering;
+use std::ops::Range;
 
 // Type invariant: Each index appears exactly once
 #[derive(Clone, Debug)]
@@ -17,7 +17,7 @@ pub struct Permutation {
 
 impl Permutation {
     /// Checks if the permutation is correct
-    #[allow(dead_code)]
+    #[allow(dead_code, clippy::result_unit_err)]
     pub fn from_indices(v: Vec<usize>) -> Result<Self, ()> {
         let perm = Permutation { indices: v };
         if perm.correct() {
@@ -79,7 +79,9 @@ where
         perm.indices.par_sort_by(|&a_label, &b_label| {
             let a = &self[[a_label, col_index]];
             let b = &self[[b_label, col_index]];
-            a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+            // NaN is the only value not comparable to itself, missing values go last
+            let is_nan = |v: &A| v.partial_cmp(v).is_none();
+            a.partial_cmp(b).unwrap_or_else(|| is_nan(a).cmp(&is_nan(b)))
         });
         perm
     }
@@ -120,7 +122,7 @@ impl PermuteArray for Array2<V> {
     }
 }
 
-/// Returns the permutation of each columns in x
+/// Returns the permutation of each columns in x, missing values are sorted last
 pub fn get_df_sorted_perm(x: &DataFrame) -> Vec<Vec<usize>> {
     let features_len = x.cols();
     let start = time::SystemTime::now();
@@ -138,3 +140,33 @@ pub fn get_df_sorted_perm(x: &DataFrame) -> Vec<Vec<usize>> {
     );
     features_order
 }
+
+/// Stably partitions every range of `indexes` so that the indexes marked in `goes_left`
+/// come first, returns the number of left indexes of each range.
+/// Ranges must not overlap.
+pub fn partition_ranges(
+    indexes: &mut [usize],
+    ranges: &[Range<usize>],
+    goes_left: &[bool],
+) -> Vec<usize> {
+    let mut right_buffer = Vec::new();
+    ranges
+        .iter()
+        .map(|range| {
+            let slice = &mut indexes[range.clone()];
+            right_buffer.clear();
+            let mut left_len = 0;
+            for i in 0..slice.len() {
+                let index = slice[i];
+                if goes_left[index] {
+                    slice[left_len] = index;
+                    left_len += 1;
+                } else {
+                    right_buffer.push(index);
+                }
+            }
+            slice[left_len..].copy_from_slice(&right_buffer);
+            left_len
+        })
+        .collect()
+}